@@ -1,34 +1,553 @@
 use anyhow::Result;
-use clap::Parser;
-use config::{Config, ConfigError, Environment, File};
-use serde::Deserialize;
+use clap::{Parser, ValueEnum};
+use config::{Config, ConfigError, Environment, File, Map};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env, fmt, fs,
+    path::Path,
+};
+use tracing_subscriber::{EnvFilter, filter::LevelFilter};
+
+const KNOWN_KEYS: &[&str] = &[
+    "debug",
+    "log.file",
+    "log.level",
+    "storage.provider",
+    "storage.path",
+    "http.connect_timeout",
+    "http.read_timeout",
+    "http.max_body_size",
+    "server.host",
+    "server.port",
+];
 
 #[derive(Parser)]
+#[command(version, about = "Prints its configuration to STDOUT")]
 struct Cli {
+    /// Enables debug mode
+    #[arg(short, long)]
+    debug: bool,
+    /// Path to configuration file
+    #[arg(short, long, env = "CONF_FILE", default_value = "config.toml")]
+    conf: String,
+    /// Validate the configuration and exit
     #[arg(long)]
-    debug: Option<bool>,
-    #[arg(long)]
-    conf: Option<String>,
+    check_config: bool,
+    /// Print the configuration in this format instead of the debug representation
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+}
+
+impl Cli {
+    /// Configuration values set on the command line, the highest-priority layer.
+    fn overrides(&self) -> Vec<(&'static str, String)> {
+        let mut overrides = Vec::new();
+        if self.debug {
+            overrides.push(("debug", "true".to_string()));
+        }
+        overrides
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default)]
 struct AppConfig {
     debug: bool,
-    log_file: String,
-    log_level: String,
+    log: LogConfig,
+    storage: StorageConfig,
+    http: HttpConfig,
+    server: ServerConfig,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+struct LogConfig {
+    file: String,
+    level: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self { file: "app.log".to_string(), level: "info".to_string() }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+struct StorageConfig {
+    provider: String,
+    path: String,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self { provider: "JSON".to_string(), path: "snippets.json".to_string() }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+struct HttpConfig {
+    connect_timeout: u64,
+    read_timeout: u64,
+    max_body_size: u64,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self { connect_timeout: 10, read_timeout: 30, max_body_size: 1024 * 1024 }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+struct ServerConfig {
+    host: String,
+    port: u16,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self { host: "127.0.0.1".to_string(), port: 8080 }
+    }
+}
+
+/// Where a configuration value was set.
+enum Location {
+    File { path: String, line: usize },
+    Env(String),
+    Cli,
+    Default,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::File { path, line } => write!(f, "{}:{}", path, line),
+            Location::Env(var) => write!(f, "env {}", var),
+            Location::Cli => write!(f, "command line"),
+            Location::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// A configuration problem pointing at the key that caused it.
+struct Problem {
+    location: Location,
+    key: String,
+    message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: `{}`: {}", self.location, self.key, self.message)
+    }
 }
 
 fn main() -> Result<()> {
     let args = Cli::parse();
-    let config_path = args.conf.unwrap_or_else(|| "config.toml".to_string());
-    let mut settings = Config::builder()
-        .set_default("debug", false)?
-        .set_default("log_file", "app.log")?
-        .set_default("log_level", "info")?
-        .add_source(File::with_name(&config_path).required(false))
-        .add_source(Environment::with_prefix("CONF"))        
-        .build()?;
-    let config: AppConfig = settings.try_deserialize()?;
-    println!("{:#?}", config);
+    let (config, warnings) = load_config(&args.conf, &env::vars().collect(), &args.overrides())?;
+    for warning in &warnings {
+        eprintln!("warning: {}", warning);
+    }
+    if args.check_config {
+        println!("{}: configuration is valid", args.conf);
+        return Ok(());
+    }
+    match args.format {
+        Some(Format::Toml) => print!("{}", toml::to_string_pretty(&config)?),
+        Some(Format::Json) => println!("{}", serde_json::to_string_pretty(&config)?),
+        Some(Format::Yaml) => print!("{}", serde_yaml::to_string(&config)?),
+        None => println!("{:#?}", config),
+    }
+    Ok(())
+}
+
+/// Loads and validates the configuration, returning it together with non-fatal warnings.
+///
+/// `env` holds the environment variables, of which those prefixed with `CONF_` override the file.
+/// `overrides` are values set on the command line and take priority over every other source.
+fn load_config(
+    config_path: &str,
+    env: &Map<String, String>,
+    overrides: &[(&str, String)],
+) -> Result<(AppConfig, Vec<Problem>)> {
+    let key_lines = fs::read_to_string(config_path)
+        .map(|source| match Path::new(config_path).extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => yaml_key_lines(&source),
+            Some("json") => json_key_lines(&source),
+            _ => toml_key_lines(&source),
+        })
+        .unwrap_or_default();
+    let locate = |key: &str| {
+        let var = env_var_name(key);
+        if overrides.iter().any(|(overridden, _)| *overridden == key) {
+            Location::Cli
+        } else if env.contains_key(&var) {
+            Location::Env(var)
+        } else if let Some(line) = key_lines.get(key) {
+            Location::File { path: config_path.to_string(), line: *line }
+        } else {
+            Location::Default
+        }
+    };
+
+    let env_source = Environment::with_prefix("CONF").prefix_separator("_").separator("__").source(Some(env.clone()));
+    let mut builder = Config::builder().add_source(File::with_name(config_path).required(false)).add_source(env_source);
+    for (key, value) in overrides {
+        builder = builder.set_override(*key, value.as_str())?;
+    }
+    let settings = builder.build()?;
+
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    for (key, line) in &key_lines {
+        let is_section = KNOWN_KEYS.iter().any(|known| known.starts_with(&format!("{}.", key)));
+        if !KNOWN_KEYS.contains(&key.as_str()) && !is_section {
+            errors.push(Problem {
+                location: Location::File { path: config_path.to_string(), line: *line },
+                key: key.clone(),
+                message: "unknown key".to_string(),
+            });
+        }
+    }
+    for var in env.keys() {
+        if var == "CONF_FILE" {
+            continue;
+        }
+        if let Some(key) = var.strip_prefix("CONF_").map(|key| key.to_lowercase().replace("__", "."))
+            && !KNOWN_KEYS.contains(&key.as_str())
+        {
+            warnings.push(Problem {
+                location: Location::Env(var.clone()),
+                key,
+                message: "unknown key, ignored".to_string(),
+            });
+        }
+    }
+    warnings.sort_by(|a, b| a.key.cmp(&b.key));
+
+    let config: AppConfig = match settings.try_deserialize() {
+        Ok(config) => config,
+        Err(ConfigError::Type { key: Some(key), unexpected, expected, .. }) => {
+            errors.push(Problem {
+                location: locate(&key),
+                message: format!("invalid type: {}, expected {}", unexpected, expected),
+                key,
+            });
+            return Err(report(errors));
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut check = |key: &str, result: std::result::Result<(), String>| {
+        if let Err(message) = result {
+            errors.push(Problem { location: locate(key), key: key.to_string(), message });
+        }
+    };
+    check("log.level", check_level(&config.log.level));
+    check("log.file", check_writable(Path::new(&config.log.file)));
+    check(
+        "storage.provider",
+        match config.storage.provider.as_str() {
+            "JSON" | "SQLITE" => Ok(()),
+            provider => Err(format!("unknown storage provider '{}', expected JSON or SQLITE", provider)),
+        },
+    );
+    check("storage.path", check_writable(Path::new(&config.storage.path)));
+    check("http.connect_timeout", positive(config.http.connect_timeout));
+    check("http.read_timeout", positive(config.http.read_timeout));
+    check("http.max_body_size", positive(config.http.max_body_size));
+    check("server.port", positive(config.server.port.into()));
+
+    if !errors.is_empty() {
+        return Err(report(errors));
+    }
+    Ok((config, warnings))
+}
+
+/// Name of the environment variable overriding `key`, e.g. `CONF_LOG__LEVEL` for `log.level`.
+fn env_var_name(key: &str) -> String {
+    format!("CONF_{}", key.replace('.', "__").to_uppercase())
+}
+
+fn positive(value: u64) -> std::result::Result<(), String> {
+    if value == 0 {
+        return Err("must be greater than 0".to_string());
+    }
     Ok(())
 }
+
+fn report(errors: Vec<Problem>) -> anyhow::Error {
+    let details: Vec<String> = errors.iter().map(|problem| format!("  {}", problem)).collect();
+    anyhow::anyhow!("invalid configuration:\n{}", details.join("\n"))
+}
+
+/// Maps every `key = value` of a TOML document to its 1-based line, with keys of `[section]`s dotted.
+fn toml_key_lines(source: &str) -> BTreeMap<String, usize> {
+    let mut keys = BTreeMap::new();
+    let mut section = String::new();
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            section = line.trim_matches(|c| c == '[' || c == ']').trim().to_string();
+            continue;
+        }
+        if let Some((key, _)) = line.split_once('=') {
+            let key = key.trim().trim_matches('"');
+            let key = if section.is_empty() { key.to_string() } else { format!("{}.{}", section, key) };
+            keys.entry(key).or_insert(index + 1);
+        }
+    }
+    keys
+}
+
+/// Maps every `key: value` of a YAML document to its 1-based line, with nested keys dotted.
+fn yaml_key_lines(source: &str) -> BTreeMap<String, usize> {
+    let mut keys = BTreeMap::new();
+    let mut parents: Vec<(usize, String)> = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('-') {
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        if let Some((key, _)) = trimmed.split_once(':') {
+            let key = key.trim().trim_matches(|c| c == '"' || c == '\'');
+            while parents.last().is_some_and(|(parent_indent, _)| *parent_indent >= indent) {
+                parents.pop();
+            }
+            let mut path: Vec<&str> = parents.iter().map(|(_, parent)| parent.as_str()).collect();
+            path.push(key);
+            keys.entry(path.join(".")).or_insert(index + 1);
+            parents.push((indent, key.to_string()));
+        }
+    }
+    keys
+}
+
+/// Maps every object key of a JSON document to its 1-based line, with nested keys dotted.
+fn json_key_lines(source: &str) -> BTreeMap<String, usize> {
+    let mut keys = BTreeMap::new();
+    let mut parents: Vec<Option<String>> = Vec::new();
+    let mut last_key: Option<String> = None;
+    let mut line = 1;
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            '"' => {
+                let mut string = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        '\n' => line += 1,
+                        c => string.push(c),
+                    }
+                }
+                while chars.peek().is_some_and(|c| c.is_whitespace() && *c != '\n') {
+                    chars.next();
+                }
+                if chars.peek() == Some(&':') {
+                    let mut path: Vec<&str> = parents.iter().flatten().map(String::as_str).collect();
+                    path.push(&string);
+                    keys.entry(path.join(".")).or_insert(line);
+                    last_key = Some(string);
+                }
+            }
+            '{' | '[' => parents.push(last_key.take()),
+            '}' | ']' => {
+                parents.pop();
+            }
+            ',' => last_key = None,
+            _ => {}
+        }
+    }
+    keys
+}
+
+/// Checks that `level` is a level such as `info`, or a filter directive list such as `info,hyper=warn`.
+///
+/// Only values with `=` or `,` are parsed as directives, since a bare word like `verbose` would
+/// otherwise be accepted as a target name.
+fn check_level(level: &str) -> std::result::Result<(), String> {
+    if level.contains(['=', ',']) {
+        return EnvFilter::try_new(level)
+            .map(|_| ())
+            .map_err(|err| format!("invalid filter directive '{}': {}", level, err));
+    }
+    level
+        .parse::<LevelFilter>()
+        .map(|_| ())
+        .map_err(|_| format!("invalid log level '{}', expected off, error, warn, info, debug or trace", level))
+}
+
+/// Checks that the file at `path`, or the directory it would be created in, is not read-only.
+///
+/// Only the metadata is inspected, nothing is created or opened for writing.
+fn check_writable(path: &Path) -> std::result::Result<(), String> {
+    if let Ok(metadata) = fs::metadata(path) {
+        if metadata.is_dir() {
+            return Err(format!("'{}' is a directory", path.display()));
+        }
+        if metadata.permissions().readonly() {
+            return Err(format!("'{}' is read-only", path.display()));
+        }
+        return Ok(());
+    }
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    match fs::metadata(parent) {
+        Ok(metadata) if !metadata.is_dir() => Err(format!("'{}' is not a directory", parent.display())),
+        Ok(metadata) if metadata.permissions().readonly() => {
+            Err(format!("directory '{}' is read-only", parent.display()))
+        }
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("directory '{}' does not exist", parent.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `content` to a temporary file with the given extension, returning its path.
+    fn config_file(name: &str, extension: &str, content: &str) -> String {
+        let path = env::temp_dir().join(format!("conf-{}-{}.{}", name, std::process::id(), extension));
+        fs::write(&path, content).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Map<String, String> {
+        vars.iter().map(|(var, value)| (var.to_string(), value.to_string())).collect()
+    }
+
+    fn errors(result: Result<(AppConfig, Vec<Problem>)>) -> String {
+        result.err().expect("configuration should be invalid").to_string()
+    }
+
+    #[test]
+    fn key_lines_are_found_in_every_format() {
+        let toml = toml_key_lines("debug = true\n\n# comment\n[log]\nlevel = \"info\"\n\"file\" = \"app.log\"\n");
+        assert_eq!(toml, BTreeMap::from([("debug".into(), 1), ("log.level".into(), 5), ("log.file".into(), 6)]));
+
+        let yaml = yaml_key_lines("debug: true\nlog:\n  # comment\n  level: info\nserver:\n  port: 80\n");
+        let expected = [("debug", 1), ("log", 2), ("log.level", 4), ("server", 5), ("server.port", 6)];
+        assert_eq!(yaml, expected.iter().map(|(key, line)| (key.to_string(), *line)).collect());
+
+        let json = json_key_lines(r#"{
+  "debug": true,
+  "log": {
+    "level": "a:b"
+  },
+  "port": 1
+}"#);
+        let expected = [("debug", 2), ("log", 3), ("log.level", 4), ("port", 6)];
+        assert_eq!(json, expected.iter().map(|(key, line)| (key.to_string(), *line)).collect());
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_with_their_location() {
+        let path = config_file("unknown", "toml", "[log]\nlevel = \"info\"\nlevle = \"debug\"\n");
+        let env = vars(&[("CONF_SERVR__PORT", "1"), ("CONF_FILE", "other.toml")]);
+        assert!(errors(load_config(&path, &env, &[])).contains(&format!("{}:3: `log.levle`: unknown key", path)));
+
+        fs::write(&path, "[log]\nlevel = \"info\"\n").unwrap();
+        let (_, warnings) = load_config(&path, &env, &[]).unwrap();
+        let warnings: Vec<String> = warnings.iter().map(ToString::to_string).collect();
+        assert_eq!(warnings, ["env CONF_SERVR__PORT: `servr.port`: unknown key, ignored"]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn invalid_values_point_at_the_source_that_set_them() {
+        let path = config_file("invalid", "yaml", "log:\n  level: verbose\nserver:\n  port: 0\n");
+        let message = errors(load_config(&path, &vars(&[]), &[]));
+        assert!(message.contains(&format!("{}:2: `log.level`: invalid log level 'verbose'", path)));
+        assert!(message.contains(&format!("{}:4: `server.port`: must be greater than 0", path)));
+
+        let message = errors(load_config(&path, &vars(&[("CONF_SERVER__PORT", "eighty")]), &[]));
+        assert!(message.contains("env CONF_SERVER__PORT: `server.port`: invalid type"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn command_line_wins_over_environment_over_file() {
+        let path = config_file("precedence", "json", r#"{"debug": false, "server": {"port": 1, "host": "file"}}"#);
+        let env = vars(&[("CONF_SERVER__PORT", "2"), ("CONF_DEBUG", "false")]);
+        let (config, _) = load_config(&path, &env, &[("debug", "true".to_string())]).unwrap();
+        assert!(config.debug);
+        assert_eq!(config.server.port, 2);
+        assert_eq!(config.server.host, "file");
+        assert_eq!(config.http.read_timeout, HttpConfig::default().read_timeout);
+
+        let args = Cli::try_parse_from(["task_3_9", "-d", "-c", &path]).unwrap();
+        let (config, _) = load_config(&args.conf, &env, &args.overrides()).unwrap();
+        assert!(config.debug);
+        let args = Cli::try_parse_from(["task_3_9", "-c", &path]).unwrap();
+        let (config, _) = load_config(&args.conf, &vars(&[("CONF_DEBUG", "true")]), &args.overrides()).unwrap();
+        assert!(config.debug);
+        let (config, _) = load_config(&args.conf, &vars(&[]), &args.overrides()).unwrap();
+        assert!(!config.debug);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn cli_follows_the_documented_contract() {
+        use clap::{CommandFactory, error::ErrorKind};
+
+        Cli::command().debug_assert();
+        let command = Cli::command();
+        assert_eq!(command.get_about().map(ToString::to_string).as_deref(), Some("Prints its configuration to STDOUT"));
+        let conf = command.get_arguments().find(|arg| arg.get_id() == "conf").unwrap();
+        assert_eq!(conf.get_short(), Some('c'));
+        assert_eq!(conf.get_env().and_then(|var| var.to_str()), Some("CONF_FILE"));
+        assert_eq!(conf.get_default_values(), ["config.toml"]);
+
+        let args = Cli::try_parse_from(["task_3_9", "-d", "-c", "other.yaml"]).unwrap();
+        assert!(args.debug);
+        assert_eq!(args.conf, "other.yaml");
+        let args = Cli::try_parse_from(["task_3_9", "--debug", "--conf", "other.json"]).unwrap();
+        assert!(args.debug);
+        assert_eq!(args.conf, "other.json");
+        assert!(Cli::try_parse_from(["task_3_9", "--debug=false"]).is_err());
+        let version = Cli::try_parse_from(["task_3_9", "--version"]).err().unwrap();
+        assert_eq!(version.kind(), ErrorKind::DisplayVersion);
+        assert_eq!(Cli::try_parse_from(["task_3_9", "-V"]).err().unwrap().kind(), ErrorKind::DisplayVersion);
+    }
+
+    #[test]
+    fn levels_and_directives_are_validated() {
+        assert!(check_level("debug").is_ok());
+        assert!(check_level("WARN").is_ok());
+        assert!(check_level("info,hyper=warn").is_ok());
+        assert!(check_level("verbose").is_err());
+        assert!(check_level("hyper=loud").is_err());
+    }
+
+    #[test]
+    fn writability_is_checked_without_creating_files() {
+        let dir = env::temp_dir();
+        let path = dir.join(format!("conf-missing-{}.log", std::process::id()));
+        assert!(check_writable(&path).is_ok());
+        assert!(!path.exists());
+        assert!(check_writable(&dir).unwrap_err().contains("is a directory"));
+        assert!(check_writable(&dir.join("no-such-dir/app.log")).unwrap_err().contains("does not exist"));
+    }
+}
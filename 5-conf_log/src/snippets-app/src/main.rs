@@ -1,7 +1,5 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
-use config::Environment;
-use reqwest::blocking::get;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::BTreeMap,
@@ -9,10 +7,13 @@ use std::{
     fs,
     io::{self, Read},
     path::Path,
+    time::Duration,
 };
 use tracing::{info, error};
-use tracing_subscriber::{FmtSubscriber, EnvFilter};
+use tracing_appender::{non_blocking::WorkerGuard, rolling::{RollingFileAppender, Rotation}};
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 use rusqlite::{params, Connection};
+use snippets_download::{download_text, DownloadOptions};
 
 #[derive(Parser)]
 struct Cli {
@@ -24,6 +25,14 @@ struct Cli {
     delete: Option<String>,
     #[arg(long)]
     download: Option<String>,
+    #[arg(long, default_value_t = 10)]
+    connect_timeout: u64,
+    #[arg(long, default_value_t = 30)]
+    read_timeout: u64,
+    #[arg(long, default_value_t = 1024 * 1024)]
+    max_size: u64,
+    #[arg(long)]
+    allow_binary: bool,
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -32,13 +41,7 @@ struct SnippetStore {
 }
 
 fn main() -> Result<()> {
-    let log_file = env::var("SNIPPETS_APP_LOG_PATH").unwrap_or_else(|_| "snippets.log".into());
-    let log_level = env::var("SNIPPETS_APP_LOG_LEVEL").unwrap_or_else(|_| "info".into());
-    let subscriber = FmtSubscriber::builder()
-        .with_env_filter(EnvFilter::new(log_level))
-        .with_writer(std::fs::File::create(&log_file)?)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
+    let _log_guard = init_logging()?;
     let args = Cli::parse();
     let storage_env = env::var("SNIPPETS_APP_STORAGE").unwrap_or_else(|_| "JSON:snippets.json".into());
 
@@ -55,11 +58,55 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Sets up logging from the `SNIPPETS_APP_LOG_*` environment variables.
+///
+/// The log file is appended to by a background thread; the returned guard flushes it when dropped.
+/// `SNIPPETS_APP_LOG_ROTATION` (`never`, `hourly`, `daily` or `weekly`) starts a new dated file
+/// per period, keeping at most `SNIPPETS_APP_LOG_MAX_FILES` of them, and `SNIPPETS_APP_LOG_STDERR=true`
+/// also writes the log to stderr. Files are rotated by time only: a file grows without limit within its period.
+fn init_logging() -> Result<WorkerGuard> {
+    let log_file = env::var("SNIPPETS_APP_LOG_PATH").unwrap_or_else(|_| "snippets.log".into());
+    let log_level = env::var("SNIPPETS_APP_LOG_LEVEL").unwrap_or_else(|_| "info".into());
+    let rotation = match env::var("SNIPPETS_APP_LOG_ROTATION").as_deref() {
+        Err(_) | Ok("never") => Rotation::NEVER,
+        Ok("hourly") => Rotation::HOURLY,
+        Ok("daily") => Rotation::DAILY,
+        Ok("weekly") => Rotation::WEEKLY,
+        Ok(other) => bail!("Unknown log rotation '{}' (expected never, hourly, daily or weekly)", other),
+    };
+    let max_files: usize = match env::var("SNIPPETS_APP_LOG_MAX_FILES") {
+        Ok(value) => value.parse().with_context(|| format!("Invalid SNIPPETS_APP_LOG_MAX_FILES '{}'", value))?,
+        Err(_) => 0,
+    };
+    let log_stderr = env::var("SNIPPETS_APP_LOG_STDERR").is_ok_and(|value| value == "true" || value == "1");
+
+    let path = Path::new(&log_file);
+    let mut builder = RollingFileAppender::builder().rotation(rotation);
+    if let Some(stem) = path.file_stem() {
+        builder = builder.filename_prefix(stem.to_string_lossy());
+    }
+    if let Some(extension) = path.extension() {
+        builder = builder.filename_suffix(extension.to_string_lossy());
+    }
+    if max_files > 0 {
+        builder = builder.max_log_files(max_files);
+    }
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let appender = builder.build(dir).with_context(|| format!("Failed to open log file {}", log_file))?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::new(log_level))
+        .with(fmt::layer().with_ansi(false).with_writer(writer))
+        .with(log_stderr.then(|| fmt::layer().with_writer(io::stderr)))
+        .init();
+    Ok(guard)
+}
+
 fn read_snippet(args: &Cli) -> Result<String> {
     if let Some(url) = &args.download {
         info!("Downloading snippet from {}", url);
-        let body = get(url).context("Failed to download snippet")?.text().context("Failed to read downloaded snippet")?;
-        Ok(body)
+        download_snippet(url, args)
     } else {
         let mut input = String::new();
         io::stdin().read_to_string(&mut input).context("Failed to read from stdin")?;
@@ -67,6 +114,18 @@ fn read_snippet(args: &Cli) -> Result<String> {
     }
 }
 
+fn download_snippet(url: &str, args: &Cli) -> Result<String> {
+    let options = DownloadOptions {
+        connect_timeout: Duration::from_secs(args.connect_timeout),
+        read_timeout: Duration::from_secs(args.read_timeout),
+        max_size: args.max_size,
+        allow_binary: args.allow_binary,
+    };
+    let text = download_text(url, &options)?;
+    info!("Downloaded {} bytes from {}", text.len(), url);
+    Ok(text)
+}
+
 fn handle_json_storage(path: &str, args: Cli) -> Result<()> {
     let mut store: SnippetStore = fs::read_to_string(path)
         .ok()
@@ -74,10 +133,10 @@ fn handle_json_storage(path: &str, args: Cli) -> Result<()> {
         .unwrap_or_default();
     let now = chrono::Utc::now().to_rfc3339();
 
-    if let Some(name) = args.name {
+    if let Some(name) = &args.name {
         let content = read_snippet(&args)?;
         store.snippets.insert(name.clone(), (content, now));
-        fs::write(path, serde_json::to_string_pretty(&store)).context("Failed to write JSON file")?;
+        fs::write(path, serde_json::to_string_pretty(&store)?).context("Failed to write JSON file")?;
         info!("Snippet '{}' saved", name);
     }
 
@@ -91,7 +150,7 @@ fn handle_json_storage(path: &str, args: Cli) -> Result<()> {
 
     if let Some(name) = args.delete {
         if store.snippets.remove(&name).is_some() {
-            fs::write(path, serde_json::to_string_pretty(&store)).context("Failed to write JSON file")?;
+            fs::write(path, serde_json::to_string_pretty(&store)?).context("Failed to write JSON file")?;
             info!("Snippet '{}' deleted", name);
         } else {
             error!("Snippet '{}' not found", name);
@@ -113,7 +172,7 @@ fn handle_sqlite_storage(path: &str, args: Cli) -> Result<()> {
     ).context("Failed to create table")?;
     let now = chrono::Utc::now().to_rfc3339();
 
-    if let Some(name) = args.name {
+    if let Some(name) = &args.name {
         let content = read_snippet(&args)?;
         conn.execute(
             "INSERT OR REPLACE INTO snippets (name, content, created_at) VALUES (?1, ?2, ?3)",
@@ -125,7 +184,7 @@ fn handle_sqlite_storage(path: &str, args: Cli) -> Result<()> {
     if let Some(name) = args.read {
         let mut stmt = conn.prepare("SELECT content FROM snippets WHERE name = ?1")?;
         let mut rows = stmt.query([name.clone()])?;
-        if let Some(row) = rows.next().transpose()? {
+        if let Some(row) = rows.next()? {
             let content: String = row.get(0)?;
             println!("{}", content);
         } else {
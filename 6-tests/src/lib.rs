@@ -1,21 +1,36 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::BTreeMap,
     fs,
-    io::{self, Read},
+    io::{self, IsTerminal, Read},
 };
 use rusqlite::{params, Connection};
 
+pub use snippets_download::{download_text, DownloadOptions};
+
 #[derive(Serialize, Deserialize, Default)]
 pub struct SnippetStore {
     pub snippets: BTreeMap<String, (String, String)>,
 }
 
 pub fn read_snippet_from_stdin() -> Result<String> {
-    let mut input = String::new();
-    io::stdin().read_to_string(&mut input).context("Failed to read from stdin")?;
-    Ok(input)
+    read_snippet_from(io::stdin())
+}
+
+pub fn read_snippet_from(input: impl Read + IsTerminal) -> Result<String> {
+    let is_terminal = input.is_terminal();
+    read_snippet(input, is_terminal)
+}
+
+/// `IsTerminal` is sealed, so tests pass the terminal check in directly.
+fn read_snippet(mut input: impl Read, is_terminal: bool) -> Result<String> {
+    if is_terminal {
+        bail!("No snippet content: pipe it into stdin (e.g. `cat file.rs | snippets-app --name NAME`) or use --download URL");
+    }
+    let mut content = String::new();
+    input.read_to_string(&mut content).context("Failed to read from stdin")?;
+    Ok(content)
 }
 
 pub fn handle_json_storage(path: &str, name: Option<String>, read: Option<String>, delete: Option<String>, content: Option<String>) -> Result<()> {
@@ -27,18 +42,18 @@ pub fn handle_json_storage(path: &str, name: Option<String>, read: Option<String
 
     if let Some(name) = name {
         store.snippets.insert(name.clone(), (content.unwrap_or_default(), now));
-        fs::write(path, serde_json::to_string_pretty(&store)).context("Failed to write JSON file")?;
+        fs::write(path, serde_json::to_string_pretty(&store)?).context("Failed to write JSON file")?;
     }
 
-    if let Some(name) = read {
-        if let Some((content, _)) = store.snippets.get(&name) {
-            println!("{}", content);
-        }
+    if let Some(name) = read
+        && let Some((content, _)) = store.snippets.get(&name)
+    {
+        println!("{}", content);
     }
 
     if let Some(name) = delete {
         store.snippets.remove(&name);
-        fs::write(path, serde_json::to_string_pretty(&store)).context("Failed to write JSON file")?;
+        fs::write(path, serde_json::to_string_pretty(&store)?).context("Failed to write JSON file")?;
     }
 
     Ok(())
@@ -66,7 +81,7 @@ pub fn handle_sqlite_storage(path: &str, name: Option<String>, read: Option<Stri
     if let Some(name) = read {
         let mut stmt = conn.prepare("SELECT content FROM snippets WHERE name = ?1")?;
         let mut rows = stmt.query([name])?;
-        if let Some(row) = rows.next().transpose()? {
+        if let Some(row) = rows.next()? {
             let content: String = row.get(0)?;
             println!("{}", content);
         }
@@ -86,8 +101,9 @@ mod tests {
 
     #[test]
     fn test_json_add_read_delete() {
-        let path = "test_snippets.json";
-        let _ = fs::remove_file(path);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snippets.json");
+        let path = path.to_str().unwrap();
         handle_json_storage(path, Some("snippet1".to_string()), None, None, Some("code1".to_string())).unwrap();
         let store: SnippetStore = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
         assert!(store.snippets.contains_key("snippet1"));
@@ -95,18 +111,33 @@ mod tests {
         handle_json_storage(path, None, None, Some("snippet1".to_string()), None).unwrap();
         let store: SnippetStore = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
         assert!(!store.snippets.contains_key("snippet1"));
-        let _ = fs::remove_file(path);
     }
 
     #[test]
     fn test_sqlite_add_read_delete() {
-        let path = "test_snippets.sqlite";
-        let _ = fs::remove_file(path);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snippets.sqlite");
+        let path = path.to_str().unwrap();
         handle_sqlite_storage(path, Some("snippet1".to_string()), None, None, Some("code1".to_string())).unwrap();
         handle_sqlite_storage(path, Some("snippet2".to_string()), None, None, Some("code2".to_string())).unwrap();
         handle_sqlite_storage(path, None, Some("snippet1".to_string()), None, None).unwrap();
         handle_sqlite_storage(path, None, None, Some("snippet1".to_string()), None).unwrap();
         handle_sqlite_storage(path, None, None, Some("snippet2".to_string()), None).unwrap();
-        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_read_snippet_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input.rs");
+        fs::write(&path, "fn main() {}").unwrap();
+        let content = read_snippet_from(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(content, "fn main() {}");
+    }
+
+    #[test]
+    fn test_read_snippet_refuses_terminal() {
+        let err = read_snippet("fn main() {}".as_bytes(), true).unwrap_err();
+        assert!(err.to_string().contains("No snippet content"));
+        assert_eq!(read_snippet("fn main() {}".as_bytes(), false).unwrap(), "fn main() {}");
     }
 }
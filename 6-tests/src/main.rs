@@ -1,7 +1,7 @@
 use anyhow::Result;
 use clap::Parser;
-use std::env;
-use snippets_app::{handle_json_storage, handle_sqlite_storage, read_snippet_from_stdin};
+use std::{env, time::Duration};
+use snippets_app::{download_text, handle_json_storage, handle_sqlite_storage, read_snippet_from_stdin, DownloadOptions};
 
 #[derive(Parser)]
 struct Cli {
@@ -11,25 +11,41 @@ struct Cli {
     read: Option<String>,
     #[arg(long)]
     delete: Option<String>,
-    #[arg(long)]
+    #[arg(long, requires = "name")]
     download: Option<String>,
+    #[arg(long, default_value_t = 10)]
+    connect_timeout: u64,
+    #[arg(long, default_value_t = 30)]
+    read_timeout: u64,
+    #[arg(long, default_value_t = 1024 * 1024)]
+    max_size: u64,
+    #[arg(long)]
+    allow_binary: bool,
 }
 
 fn main() -> Result<()> {
     let args = Cli::parse();
     let storage_env = env::var("SNIPPETS_APP_STORAGE").unwrap_or_else(|_| "JSON:snippets.json".into());
-    let content = if args.download.is_some() {
-        reqwest::blocking::get(args.download.unwrap())?.text()?
+    let content = if args.name.is_none() {
+        None
+    } else if let Some(url) = &args.download {
+        let options = DownloadOptions {
+            connect_timeout: Duration::from_secs(args.connect_timeout),
+            read_timeout: Duration::from_secs(args.read_timeout),
+            max_size: args.max_size,
+            allow_binary: args.allow_binary,
+        };
+        Some(download_text(url, &options)?)
     } else {
-        read_snippet_from_stdin()?
+        Some(read_snippet_from_stdin()?)
     };
 
     if storage_env.starts_with("JSON:") {
         let path = storage_env.trim_start_matches("JSON:");
-        handle_json_storage(path, args.name, args.read, args.delete, Some(content))?;
+        handle_json_storage(path, args.name, args.read, args.delete, content)?;
     } else if storage_env.starts_with("SQLITE:") {
         let path = storage_env.trim_start_matches("SQLITE:");
-        handle_sqlite_storage(path, args.name, args.read, args.delete, Some(content))?;
+        handle_sqlite_storage(path, args.name, args.read, args.delete, content)?;
     }
 
     Ok(())
@@ -0,0 +1,193 @@
+//! Append-only audit trail of snippet mutations.
+//!
+//! Both storage backends record an [`AuditEntry`] for every create, update and delete:
+//! SQLite in the `audit_log` table, the JSON backend in a `<store>.audit.jsonl` sidecar file.
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{env, fmt, str::FromStr};
+
+/// Kind of a recorded mutation.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOperation {
+    /// A new snippet was saved.
+    Create,
+    /// An existing snippet was saved again.
+    Update,
+    /// A snippet was deleted.
+    Delete,
+}
+
+impl fmt::Display for AuditOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Create => "create",
+            Self::Update => "update",
+            Self::Delete => "delete",
+        })
+    }
+}
+
+impl FromStr for AuditOperation {
+    type Err = anyhow::Error;
+
+    fn from_str(operation: &str) -> Result<Self> {
+        match operation {
+            "create" => Ok(Self::Create),
+            "update" => Ok(Self::Update),
+            "delete" => Ok(Self::Delete),
+            _ => bail!("Unknown audit operation '{operation}'"),
+        }
+    }
+}
+
+/// A single mutation of the store.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// Time of the mutation in RFC 3339 format.
+    pub timestamp: String,
+    /// Name of the user that made the change.
+    pub user: String,
+    /// Host the change was made on.
+    pub host: String,
+    /// Kind of the mutation.
+    pub operation: AuditOperation,
+    /// Name of the snippet.
+    pub name: String,
+    /// SHA-256 of the content before the change, if the snippet existed.
+    pub hash_before: Option<String>,
+    /// SHA-256 of the content after the change, unless it was deleted.
+    pub hash_after: Option<String>,
+}
+
+impl AuditEntry {
+    /// Records a change of snippet `name` from content `before` to `after` by the current user.
+    ///
+    /// The operation follows from which of the two contents are present.
+    pub fn new(name: &str, before: Option<&str>, after: Option<&str>) -> Self {
+        let operation = match (before, after) {
+            (None, _) => AuditOperation::Create,
+            (Some(_), Some(_)) => AuditOperation::Update,
+            (Some(_), None) => AuditOperation::Delete,
+        };
+        Self {
+            timestamp: Utc::now().to_rfc3339(),
+            user: current_user(),
+            host: gethostname::gethostname().to_string_lossy().into_owned(),
+            operation,
+            name: name.to_owned(),
+            hash_before: before.map(content_hash),
+            hash_after: after.map(content_hash),
+        }
+    }
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Entries are read back from the store, so a hash may be shorter than expected.
+        let short = |hash: &Option<String>| {
+            hash.as_deref()
+                .map_or("-", |hash| hash.get(..12).unwrap_or(hash))
+                .to_owned()
+        };
+        write!(
+            f,
+            "{}  {}@{}  {:<6}  {}  {} -> {}",
+            self.timestamp,
+            self.user,
+            self.host,
+            self.operation,
+            self.name,
+            short(&self.hash_before),
+            short(&self.hash_after)
+        )
+    }
+}
+
+/// Criteria for `audit` queries; unset criteria match every entry.
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    /// Only entries for this snippet.
+    pub name: Option<String>,
+    /// Only entries made by this user.
+    pub user: Option<String>,
+    /// Only entries made at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Only entries made before this time.
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl AuditFilter {
+    /// Checks whether `entry` satisfies every criterion.
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        let timestamp = DateTime::parse_from_rfc3339(&entry.timestamp).map(|time| time.with_timezone(&Utc));
+        self.name.as_ref().is_none_or(|name| *name == entry.name)
+            && self.user.as_ref().is_none_or(|user| *user == entry.user)
+            && self.since.is_none_or(|since| timestamp.is_ok_and(|time| time >= since))
+            && self.until.is_none_or(|until| timestamp.is_ok_and(|time| time < until))
+    }
+}
+
+/// Hex-encoded SHA-256 of snippet content.
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// Parses an RFC 3339 time or a `YYYY-MM-DD` date (midnight UTC), as accepted by `audit --since/--until`.
+pub fn parse_time(time: &str) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(time, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).expect("midnight is valid").and_utc());
+    }
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| time.with_timezone(&Utc))
+        .with_context(|| format!("Invalid time '{time}' (expected YYYY-MM-DD or RFC 3339)"))
+}
+
+fn current_user() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("LOGNAME"))
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operation_follows_from_contents() {
+        assert_eq!(AuditEntry::new("a", None, Some("x")).operation, AuditOperation::Create);
+        assert_eq!(AuditEntry::new("a", Some("x"), Some("y")).operation, AuditOperation::Update);
+        let deleted = AuditEntry::new("a", Some("x"), None);
+        assert_eq!(deleted.operation, AuditOperation::Delete);
+        assert_eq!(deleted.hash_before.as_deref(), Some(content_hash("x").as_str()));
+        assert_eq!(deleted.hash_after, None);
+    }
+
+    #[test]
+    fn filter_matches_name_user_and_time() {
+        let mut entry = AuditEntry::new("a", None, Some("x"));
+        entry.user = "alice".into();
+        entry.timestamp = "2024-03-10T12:00:00+00:00".into();
+        let filter = AuditFilter {
+            name: Some("a".into()),
+            user: Some("alice".into()),
+            since: Some(parse_time("2024-03-10").unwrap()),
+            until: Some(parse_time("2024-03-11").unwrap()),
+        };
+        assert!(filter.matches(&entry));
+        assert!(!AuditFilter { user: Some("bob".into()), ..filter.clone() }.matches(&entry));
+        assert!(!AuditFilter { until: Some(parse_time("2024-03-10").unwrap()), ..filter }.matches(&entry));
+    }
+
+    #[test]
+    fn display_shortens_hashes_of_any_length() {
+        let mut entry = AuditEntry::new("a", Some("x"), Some("y"));
+        entry.hash_before = Some("abc".into());
+        let line = entry.to_string();
+        assert!(line.ends_with(&format!("abc -> {}", &content_hash("y")[..12])), "{line}");
+    }
+}
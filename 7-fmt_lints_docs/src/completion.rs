@@ -0,0 +1,124 @@
+//! Shell completion of snippet names from the active store.
+//!
+//! The completion scripts printed by `completions <shell>` call back into the binary on every
+//! <TAB>, so names are completed from the store selected by the configuration, including
+//! `SNIPPETS_APP_STORAGE` and a `--storage` already on the command line.
+
+use anyhow::Result;
+use clap_complete::engine::CompletionCandidate;
+use std::{env, ffi::OsStr, path::Path};
+
+use crate::{
+    crypto::{PASSPHRASE_VAR, PassphraseSource},
+    name::browse,
+    settings::{SnippetsConfig, load_config},
+    storage::{SnippetStorage, open_storage_read_only},
+};
+
+/// Environment variable naming the shell when the binary is called back to complete.
+pub const COMPLETE_VAR: &str = "COMPLETE";
+
+/// Completes the snippet name or namespace starting with `current`, e.g. `rust/` to `rust/patterns/`.
+///
+/// Namespaces below `current` are collapsed like in `list`, so each <TAB> descends one level.
+/// Completing never fails: a missing or locked store simply completes nothing.
+pub fn complete_names(current: &OsStr) -> Vec<CompletionCandidate> {
+    complete(current, |storage| storage.names())
+}
+
+/// Completes the name of a snippet in the trash starting with `current`, for `restore`.
+pub fn complete_trashed_names(current: &OsStr) -> Vec<CompletionCandidate> {
+    complete(current, |storage| Ok(storage.trash()?.into_iter().map(|trashed| trashed.name).collect()))
+}
+
+/// Completes `current` from the names listed by `list` in the configured store.
+fn complete(current: &OsStr, list: fn(&dyn SnippetStorage) -> Result<Vec<String>>) -> Vec<CompletionCandidate> {
+    let overrides: Vec<_> = storage_arg(env::args().skip_while(|arg| arg != "--"))
+        .map(|storage| ("storage", storage))
+        .into_iter()
+        .collect();
+    complete_with(current, &overrides, list)
+}
+
+/// Completes `current` from the store configured with the command line `overrides`.
+fn complete_with(
+    current: &OsStr,
+    overrides: &[(&str, String)],
+    list: fn(&dyn SnippetStorage) -> Result<Vec<String>>,
+) -> Vec<CompletionCandidate> {
+    let Some(current) = current.to_str() else {
+        return Vec::new();
+    };
+    let Ok(loaded) = load_config(overrides) else {
+        return Vec::new();
+    };
+    let names = stored_names(&loaded.config, list).unwrap_or_default();
+    browse(&names, current, false, loaded.config.names.ignore_case)
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// Names listed by `list` in the configured store.
+///
+/// The store is opened read-only, without prompting, so that completing never creates or migrates it.
+fn stored_names(config: &SnippetsConfig, list: fn(&dyn SnippetStorage) -> Result<Vec<String>>) -> Result<Vec<String>> {
+    if !Path::new(config.storage_path()).exists() {
+        return Ok(Vec::new());
+    }
+    list(open_storage_read_only(&config.storage, PassphraseSource::from_env(PASSPHRASE_VAR))?.as_ref())
+}
+
+/// Value of the `--storage` option among the words being completed.
+fn storage_arg(mut words: impl Iterator<Item = String>) -> Option<String> {
+    let mut storage = None;
+    while let Some(word) = words.next() {
+        if word == "--storage" {
+            storage = words.next();
+        } else if let Some(value) = word.strip_prefix("--storage=") {
+            storage = Some(value.to_owned());
+        }
+    }
+    storage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{Snippet, SqliteStorage};
+
+    #[test]
+    fn storage_option_is_found_among_the_words() {
+        let words = |line: &str| line.split(' ').map(str::to_owned).collect::<Vec<_>>().into_iter();
+        assert_eq!(storage_arg(words("snippets-app show rust/")), None);
+        assert_eq!(storage_arg(words("snippets-app --storage JSON:a.json show r")).as_deref(), Some("JSON:a.json"));
+        assert_eq!(storage_arg(words("snippets-app show --storage=SQLITE:b.db r")).as_deref(), Some("SQLITE:b.db"));
+    }
+
+    #[test]
+    fn names_are_completed_from_the_configured_store() {
+        let path = env::temp_dir().join(format!("snippets-completion-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut storage = SqliteStorage::open(&path).unwrap();
+        for name in ["go/errors", "rust/hello", "rust/patterns/let-else", "rust/patterns/newtype"] {
+            storage.save(&name.parse().unwrap(), &Snippet::new("code".into())).unwrap();
+        }
+        storage.remove("go/errors").unwrap();
+        drop(storage);
+
+        let overrides = [("storage", format!("SQLITE:{}", path.display()))];
+        let complete = |current: &str, list| -> Vec<String> {
+            complete_with(OsStr::new(current), &overrides, list)
+                .iter()
+                .map(|candidate| candidate.get_value().to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(complete("rust/", |storage| storage.names()), ["rust/hello", "rust/patterns/"]);
+        assert_eq!(complete("", |storage| storage.names()), ["rust/"]);
+        let trashed = |storage: &dyn SnippetStorage| {
+            Ok(storage.trash()?.into_iter().map(|trashed| trashed.name).collect())
+        };
+        assert_eq!(complete("go/", trashed), ["go/errors"]);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,348 @@
+//! Encryption of snippet content at rest.
+//!
+//! The key is derived from a passphrase with Argon2id and used with XChaCha20-Poly1305.
+//! Every encrypted store carries an [`EncryptionHeader`] with the salt, the key derivation
+//! parameters and a known value encrypted with the key. Decrypting that value tells a wrong
+//! passphrase ([`CryptoError::WrongPassphrase`]) apart from damaged data ([`CryptoError::Corrupted`]).
+//!
+//! A store encrypts either all snippet content or only that of private snippets, see [`EncryptionScope`].
+
+use anyhow::{Context, Result, anyhow, bail};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use chacha20poly1305::{
+    AeadCore, KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, OsRng, rand_core::RngCore},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::OnceCell,
+    env, fmt,
+    io::{self, IsTerminal},
+};
+
+/// Environment variable with the passphrase of an encrypted store.
+pub const PASSPHRASE_VAR: &str = "SNIPPETS_APP_PASSPHRASE";
+
+/// Environment variable with the new passphrase for `rekey` and `init --encrypt`.
+pub const NEW_PASSPHRASE_VAR: &str = "SNIPPETS_APP_NEW_PASSPHRASE";
+
+const KEY_CHECK: &[u8] = b"snippets-app key check";
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+
+/// Errors of encrypted stores that callers may want to tell apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CryptoError {
+    /// The passphrase does not match the one the store was encrypted with.
+    WrongPassphrase,
+    /// Encrypted data could not be decrypted with the right key.
+    Corrupted(String),
+    /// Encrypted data was accessed, but no passphrase is available.
+    Locked,
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongPassphrase => write!(f, "Wrong passphrase"),
+            Self::Corrupted(what) => write!(f, "Encrypted data is corrupted: {what}"),
+            Self::Locked => write!(
+                f,
+                "The store is encrypted: set {PASSPHRASE_VAR} or run the command in a terminal to enter the passphrase"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+/// Which snippet content of a store is encrypted.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptionScope {
+    /// The content of every snippet.
+    All,
+    /// Only the content of snippets saved with `--private`.
+    Private,
+}
+
+impl fmt::Display for EncryptionScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::All => "all",
+            Self::Private => "private",
+        })
+    }
+}
+
+/// Key derivation parameters and key check of an encrypted store.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EncryptionHeader {
+    /// Argon2id salt, base64-encoded.
+    pub salt: String,
+    /// Argon2 memory cost in KiB.
+    pub m_cost: u32,
+    /// Argon2 number of iterations.
+    pub t_cost: u32,
+    /// Argon2 degree of parallelism.
+    pub p_cost: u32,
+    /// A known value encrypted with the key, base64-encoded.
+    pub check: String,
+    /// Which snippet content is encrypted.
+    pub scope: EncryptionScope,
+}
+
+/// A key derived from a passphrase.
+pub struct Cipher {
+    aead: XChaCha20Poly1305,
+}
+
+impl Cipher {
+    /// Derives a key from `passphrase` with a new random salt and returns it with the header describing it.
+    pub fn create(passphrase: &str, scope: EncryptionScope) -> Result<(Self, EncryptionHeader)> {
+        Self::create_with(passphrase, scope, Params::default())
+    }
+
+    fn create_with(passphrase: &str, scope: EncryptionScope, params: Params) -> Result<(Self, EncryptionHeader)> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let cipher = Self::derive(passphrase, &salt, params.m_cost(), params.t_cost(), params.p_cost())?;
+        let header = EncryptionHeader {
+            salt: BASE64.encode(salt),
+            m_cost: params.m_cost(),
+            t_cost: params.t_cost(),
+            p_cost: params.p_cost(),
+            check: cipher.encrypt(KEY_CHECK),
+            scope,
+        };
+        Ok((cipher, header))
+    }
+
+    /// Derives the key described by `header`, failing with [`CryptoError::WrongPassphrase`] if it does not match.
+    ///
+    /// A header that cannot be decoded fails with [`CryptoError::Corrupted`] instead, before any key is derived.
+    pub fn unlock(header: &EncryptionHeader, passphrase: &str) -> Result<Self> {
+        let salt = BASE64
+            .decode(&header.salt)
+            .map_err(|_| CryptoError::Corrupted("invalid salt in the encryption header".into()))?;
+        let check = BASE64
+            .decode(&header.check)
+            .ok()
+            .filter(|check| check.len() >= NONCE_LEN + TAG_LEN)
+            .ok_or_else(|| CryptoError::Corrupted("invalid key check in the encryption header".into()))?;
+        let cipher = Self::derive(passphrase, &salt, header.m_cost, header.t_cost, header.p_cost)?;
+        let (nonce, ciphertext) = check.split_at(NONCE_LEN);
+        match cipher.aead.decrypt(XNonce::from_slice(nonce), ciphertext) {
+            Ok(check) if check == KEY_CHECK => Ok(cipher),
+            _ => Err(CryptoError::WrongPassphrase.into()),
+        }
+    }
+
+    fn derive(passphrase: &str, salt: &[u8], m_cost: u32, t_cost: u32, p_cost: u32) -> Result<Self> {
+        let params = Params::new(m_cost, t_cost, p_cost, Some(32))
+            .map_err(|err| anyhow!("Invalid key derivation parameters: {err}"))?;
+        let mut key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|err| anyhow!("Failed to derive key: {err}"))?;
+        Ok(Self {
+            aead: XChaCha20Poly1305::new(&key.into()),
+        })
+    }
+
+    /// Encrypts `plaintext` with a random nonce, returning base64 of the nonce and the ciphertext.
+    pub fn encrypt(&self, plaintext: &[u8]) -> String {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.aead.encrypt(&nonce, plaintext).expect("encryption does not fail");
+        BASE64.encode([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    /// Decrypts the output of [`encrypt`](Self::encrypt).
+    pub fn decrypt(&self, data: &str) -> Result<Vec<u8>, CryptoError> {
+        let bytes = BASE64
+            .decode(data)
+            .map_err(|_| CryptoError::Corrupted("invalid base64".into()))?;
+        if bytes.len() < NONCE_LEN {
+            return Err(CryptoError::Corrupted("truncated data".into()));
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        self.aead
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| CryptoError::Corrupted("authentication failed".into()))
+    }
+
+    /// Encrypts snippet content.
+    pub fn encrypt_str(&self, plaintext: &str) -> String {
+        self.encrypt(plaintext.as_bytes())
+    }
+
+    /// Decrypts snippet content encrypted with [`encrypt_str`](Self::encrypt_str).
+    pub fn decrypt_str(&self, data: &str) -> Result<String> {
+        let plaintext = self.decrypt(data)?;
+        String::from_utf8(plaintext).map_err(|_| CryptoError::Corrupted("content is not UTF-8".into()).into())
+    }
+}
+
+/// The encryption header of a store, with the key derived on first use.
+///
+/// Deriving the key is deliberately slow and may prompt for the passphrase, so it only happens
+/// once encrypted content is actually accessed.
+pub struct Keyring {
+    header: EncryptionHeader,
+    passphrase: PassphraseSource,
+    cipher: OnceCell<Cipher>,
+}
+
+impl Keyring {
+    /// A keyring deriving the key for `header` from `passphrase` when needed.
+    pub fn new(header: EncryptionHeader, passphrase: PassphraseSource) -> Self {
+        Self {
+            header,
+            passphrase,
+            cipher: OnceCell::new(),
+        }
+    }
+
+    /// A keyring with an already derived key, as returned by [`Cipher::create`].
+    pub fn unlocked(header: EncryptionHeader, cipher: Cipher) -> Self {
+        Self {
+            header,
+            passphrase: PassphraseSource::None,
+            cipher: OnceCell::from(cipher),
+        }
+    }
+
+    /// Creates a key from a new passphrase.
+    pub fn create(passphrase: &str, scope: EncryptionScope) -> Result<Self> {
+        let (cipher, header) = Cipher::create(passphrase, scope)?;
+        Ok(Self::unlocked(header, cipher))
+    }
+
+    /// The header to store with the encrypted content.
+    pub fn header(&self) -> &EncryptionHeader {
+        &self.header
+    }
+
+    /// Which snippet content is encrypted.
+    pub fn scope(&self) -> EncryptionScope {
+        self.header.scope
+    }
+
+    /// Returns the key, deriving it from the passphrase on first use.
+    pub fn cipher(&self) -> Result<&Cipher> {
+        if let Some(cipher) = self.cipher.get() {
+            return Ok(cipher);
+        }
+        let cipher = Cipher::unlock(&self.header, &self.passphrase.get()?)?;
+        Ok(self.cipher.get_or_init(|| cipher))
+    }
+}
+
+/// Where the passphrase comes from when encrypted data is accessed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum PassphraseSource {
+    /// Encrypted data cannot be accessed.
+    #[default]
+    None,
+    /// A known passphrase.
+    Value(String),
+    /// Ask on the terminal when it is first needed.
+    Prompt,
+}
+
+impl PassphraseSource {
+    /// Takes the passphrase from the environment variable `var`, if set.
+    pub fn from_env(var: &str) -> Self {
+        env::var(var).map_or(Self::None, Self::Value)
+    }
+
+    /// Prompts for the passphrase if there is none and stderr is a terminal.
+    pub fn or_prompt(self) -> Self {
+        match self {
+            Self::None if io::stderr().is_terminal() => Self::Prompt,
+            source => source,
+        }
+    }
+
+    /// Returns the passphrase of an existing store.
+    pub fn get(&self) -> Result<String> {
+        match self {
+            Self::None => Err(CryptoError::Locked.into()),
+            Self::Value(passphrase) => Ok(passphrase.clone()),
+            Self::Prompt => rpassword::prompt_password("Passphrase: ").context("Failed to read passphrase"),
+        }
+    }
+
+    /// Returns a new passphrase, asking twice when prompting.
+    pub fn get_new(&self) -> Result<String> {
+        let passphrase = match self {
+            Self::None => bail!("No new passphrase: set {NEW_PASSPHRASE_VAR} or run the command in a terminal"),
+            Self::Value(passphrase) => passphrase.clone(),
+            Self::Prompt => {
+                let prompt = |prompt| rpassword::prompt_password(prompt).context("Failed to read passphrase");
+                let passphrase = prompt("New passphrase: ")?;
+                let repeated = prompt("Repeat new passphrase: ")?;
+                if passphrase != repeated {
+                    bail!("The passphrases do not match");
+                }
+                passphrase
+            }
+        };
+        if passphrase.is_empty() {
+            bail!("The passphrase must not be empty");
+        }
+        Ok(passphrase)
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_cipher(passphrase: &str, scope: EncryptionScope) -> (Cipher, EncryptionHeader) {
+    let params = Params::new(Params::MIN_M_COST, Params::MIN_T_COST, 1, Some(32)).unwrap();
+    Cipher::create_with(passphrase, scope, params).unwrap()
+}
+
+/// A keyring with cheap key derivation, for tests.
+#[cfg(test)]
+pub(crate) fn test_keyring(passphrase: &str, scope: EncryptionScope) -> Keyring {
+    let (cipher, header) = test_cipher(passphrase, scope);
+    Keyring::unlocked(header, cipher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrong_passphrase_is_not_corruption() {
+        let (cipher, header) = test_cipher("secret", EncryptionScope::All);
+        let data = cipher.encrypt_str("fn main() {}");
+        let unlocked = Cipher::unlock(&header, "secret").unwrap();
+        assert_eq!(unlocked.decrypt_str(&data).unwrap(), "fn main() {}");
+
+        let err = Cipher::unlock(&header, "guess").err().unwrap();
+        assert_eq!(err.downcast_ref::<CryptoError>(), Some(&CryptoError::WrongPassphrase));
+
+        let mut damaged = BASE64.decode(&data).unwrap();
+        *damaged.last_mut().unwrap() ^= 1;
+        let err = unlocked.decrypt_str(&BASE64.encode(damaged)).unwrap_err();
+        assert!(matches!(err.downcast_ref::<CryptoError>(), Some(CryptoError::Corrupted(_))));
+    }
+
+    #[test]
+    fn damaged_key_check_is_corruption() {
+        let (_, header) = test_cipher("secret", EncryptionScope::All);
+        let check = BASE64.decode(&header.check).unwrap();
+        for damaged in ["not base64!".to_owned(), BASE64.encode(&check[..NONCE_LEN + TAG_LEN - 1])] {
+            let header = EncryptionHeader { check: damaged, ..header.clone() };
+            let err = Cipher::unlock(&header, "secret").err().unwrap();
+            assert!(matches!(err.downcast_ref::<CryptoError>(), Some(CryptoError::Corrupted(_))), "{err}");
+        }
+
+        let mut flipped = check;
+        *flipped.last_mut().unwrap() ^= 1;
+        let header = EncryptionHeader { check: BASE64.encode(flipped), ..header };
+        let err = Cipher::unlock(&header, "secret").err().unwrap();
+        assert_eq!(err.downcast_ref::<CryptoError>(), Some(&CryptoError::WrongPassphrase));
+    }
+}
@@ -0,0 +1,182 @@
+//! Downloading snippet content over HTTP(S) and from local files.
+
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::{StatusCode, Url};
+use snippets_download::{Fetched, Validators, fetch, read_limited};
+use std::{
+    fs::{self, File},
+    path::Path,
+};
+
+use crate::{
+    metadata::{describe_file, language_for_file},
+    resolve::{extract_lines, resolve_url},
+    storage::{Snippet, Source, SourceKind},
+};
+
+pub use snippets_download::DownloadOptions;
+
+/// Downloaded snippet content together with the cache validators of its source.
+#[derive(Debug, Clone)]
+pub struct Download {
+    /// Decoded snippet content.
+    pub content: String,
+    /// Source to record alongside the snippet.
+    pub source: Source,
+    /// Name of the downloaded file, if it can be told from the URL.
+    pub file_name: Option<String>,
+    /// Line range extracted from the downloaded file.
+    pub lines: Option<(usize, usize)>,
+}
+
+impl From<Download> for Snippet {
+    fn from(download: Download) -> Self {
+        let mut snippet = Snippet::new(download.content);
+        if let Some(file_name) = &download.file_name {
+            snippet.language = language_for_file(file_name).map(str::to_owned);
+            snippet.description = Some(describe_file(file_name, download.lines));
+        }
+        snippet.source_kind = Some(if download.source.url.starts_with("file:") {
+            SourceKind::File
+        } else {
+            SourceKind::Url
+        });
+        snippet.source = Some(download.source);
+        snippet
+    }
+}
+
+/// Downloads the snippet behind `url` and decodes it as text.
+///
+/// Non-2xx responses, bodies larger than [`DownloadOptions::max_size`] and
+/// non-text content types (unless [`DownloadOptions::allow_binary`] is set)
+/// are rejected instead of being stored as a snippet.
+pub fn download_snippet(url: &str, options: &DownloadOptions) -> Result<Download> {
+    let source = Source {
+        url: url.to_owned(),
+        etag: None,
+        last_modified: None,
+    };
+    match fetch_snippet(&source, options)? {
+        Some(download) => Ok(download),
+        None => bail!("Server responded with {} for {url}", StatusCode::NOT_MODIFIED),
+    }
+}
+
+/// Builds the `file://` URL of a local file, with an optional 1-based line range as `#L10-L40` anchor.
+pub fn file_url(path: &Path, lines: Option<(usize, usize)>) -> Result<String> {
+    let path = fs::canonicalize(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut url = Url::from_file_path(&path).map_err(|()| anyhow!("Cannot build a URL for {}", path.display()))?;
+    if let Some((start, end)) = lines {
+        url.set_fragment(Some(&format!("L{start}-L{end}")));
+    }
+    Ok(url.into())
+}
+
+/// Downloads `source` using a conditional request built from its validators.
+///
+/// Links to gist, GitHub, GitLab and paste-bin pages are resolved to their raw content
+/// (see [`resolve_url`]) and line anchors are applied to the downloaded file.
+/// `file://` URLs are read from the local file system.
+/// Returns `None` if the source reports that the content has not been modified.
+pub fn fetch_snippet(source: &Source, options: &DownloadOptions) -> Result<Option<Download>> {
+    let resolved = resolve_url(&source.url)?;
+    let validators = Validators {
+        etag: source.etag.clone(),
+        last_modified: source.last_modified.clone(),
+    };
+    let fetched = if resolved.raw_url.starts_with("file:") {
+        fetch_file(&resolved.raw_url, &validators, options)?
+    } else {
+        fetch(&resolved.raw_url, &validators, options)?
+    };
+    let Some(fetched) = fetched else {
+        return Ok(None);
+    };
+
+    let mut content = fetched.decode(options)?;
+    if let Some(lines) = resolved.lines {
+        content = extract_lines(&content, lines)?;
+    }
+    Ok(Some(Download {
+        content,
+        source: Source {
+            url: source.url.clone(),
+            etag: fetched.validators.etag,
+            last_modified: fetched.validators.last_modified,
+        },
+        file_name: resolved.file_name,
+        lines: resolved.lines,
+    }))
+}
+
+/// Reads a local file, using its modification time in place of `Last-Modified`.
+///
+/// The time is kept with nanoseconds, so that edits within the same second are not taken as unchanged.
+fn fetch_file(url: &str, validators: &Validators, options: &DownloadOptions) -> Result<Option<Fetched>> {
+    let path = Url::parse(url)
+        .ok()
+        .and_then(|url| url.to_file_path().ok())
+        .with_context(|| format!("Invalid file URL '{url}'"))?;
+    let file = File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let metadata = file
+        .metadata()
+        .with_context(|| format!("Failed to read metadata of {}", path.display()))?;
+    if !metadata.is_file() {
+        bail!("{} is not a regular file", path.display());
+    }
+    let last_modified = metadata
+        .modified()
+        .ok()
+        .map(|modified| DateTime::<Utc>::from(modified).to_rfc3339_opts(SecondsFormat::Nanos, true));
+    if last_modified.is_some() && validators.last_modified == last_modified {
+        return Ok(None);
+    }
+    if metadata.len() > options.max_size {
+        bail!("{} is {} bytes, which exceeds the limit of {} bytes", path.display(), metadata.len(), options.max_size);
+    }
+
+    let body = read_limited(file, url, options)?;
+    if !options.allow_binary && body.contains(&0) {
+        bail!("{} looks binary (use --allow-binary to override)", path.display());
+    }
+    Ok(Some(Fetched {
+        body,
+        charset: None,
+        validators: Validators {
+            etag: None,
+            last_modified,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn files_changed_within_a_second_are_fetched_again() {
+        let path = std::env::temp_dir().join(format!("snippets-download-{}.rs", std::process::id()));
+        let written_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let write = |content: &str, modified: SystemTime| {
+            fs::write(&path, content).unwrap();
+            File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        };
+        write("fn a() {}", written_at);
+        let source = Source {
+            url: file_url(&path, None).unwrap(),
+            etag: None,
+            last_modified: None,
+        };
+        let options = DownloadOptions::default();
+        let first = fetch_snippet(&source, &options).unwrap().unwrap();
+        assert!(fetch_snippet(&first.source, &options).unwrap().is_none());
+
+        write("fn b() {}", written_at + Duration::from_millis(300));
+        let second = fetch_snippet(&first.source, &options).unwrap().unwrap();
+        assert_eq!(second.content, "fn b() {}");
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,30 @@
+//! Editing snippet content in the editor of the user, for `tui` and `--editor`.
+
+use anyhow::{Context, Result, bail};
+use std::{env, fs, io::Write, process};
+
+/// Lets the user edit `content` in `$VISUAL` or `$EDITOR`, falling back to `vi`.
+pub fn edit_in_editor(name: &str, content: &str) -> Result<String> {
+    let base_name = name.rsplit('/').next().unwrap_or(name);
+    let path = env::temp_dir().join(format!("snippets-app-{}-{base_name}", process::id()));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    let editor = env::var("VISUAL").or_else(|_| env::var("EDITOR")).unwrap_or_else(|_| "vi".to_owned());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = process::Command::new(program).args(words).arg(&path).status();
+    let edited = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    let status = status.with_context(|| format!("Failed to run editor '{editor}'"))?;
+    if !status.success() {
+        bail!("Editor '{editor}' failed with {status}");
+    }
+    edited.with_context(|| format!("Failed to read {}", path.display()))
+}
@@ -1,100 +1,149 @@
 //! Library for snippets-app, including JSON and SQLite storage.
 
-use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
-use std::{
-    collections::BTreeMap,
-    fs,
-    io::{self, Read},
-};
-use rusqlite::{params, Connection};
+use anyhow::{Context, Result, bail};
+use std::io::{self, IsTerminal, Read};
 
-/// Represents the snippet store for JSON storage.
-#[derive(Serialize, Deserialize, Default)]
-pub struct SnippetStore {
-    pub snippets: BTreeMap<String, (String, String)>,
-}
+pub mod audit;
+pub mod completion;
+pub mod crypto;
+pub mod download;
+pub mod editor;
+pub mod logging;
+pub mod metadata;
+pub mod metrics;
+pub mod name;
+pub mod picker;
+pub mod reference;
+pub mod reload;
+pub mod resolve;
+pub mod secrets;
+pub mod select;
+pub mod server;
+pub mod settings;
+pub mod storage;
+pub mod tui;
+pub mod usage;
+
+use download::{DownloadOptions, fetch_snippet};
+pub use name::{NotFound, SnippetName};
+use secrets::SecretScanner;
+pub use storage::{
+    JsonStorage, Snippet, SnippetStorage, SnippetStore, Source, SourceKind, SqliteStorage, open_storage,
+};
 
 /// Reads snippet content from stdin.
+///
+/// Fails instead of waiting for EOF when stdin is an interactive terminal.
 pub fn read_snippet_from_stdin() -> Result<String> {
-    let mut input = String::new();
-    io::stdin()
-        .read_to_string(&mut input)
+    read_snippet_from(io::stdin())
+}
+
+/// Reads snippet content from `input`, refusing to block on an interactive terminal.
+pub fn read_snippet_from(mut input: impl Read + IsTerminal) -> Result<String> {
+    if input.is_terminal() {
+        bail!(
+            "No snippet content: pipe it into stdin (e.g. `cat file.rs | snippets-app --name NAME`) \
+             or use --download URL / --file PATH"
+        );
+    }
+    let mut content = String::new();
+    input
+        .read_to_string(&mut content)
         .context("Failed to read from stdin")?;
-    Ok(input)
+    Ok(content)
 }
 
 /// Handles snippet operations in JSON storage.
 pub fn handle_json_storage(
     path: &str,
-    name: Option<String>,
+    name: Option<SnippetName>,
     read: Option<String>,
     delete: Option<String>,
     content: Option<String>,
 ) -> Result<()> {
-    let mut store: SnippetStore =
-        fs::read_to_string(path).ok().and_then(|s| serde_json::from_str(&s).ok()).unwrap_or_default();
-    let now = chrono::Utc::now().to_rfc3339();
-
-    if let Some(name) = name {
-        store.snippets.insert(name.clone(), (content.unwrap_or_default(), now));
-        fs::write(path, serde_json::to_string_pretty(&store)).context("Failed to write JSON file")?;
-    }
-
-    if let Some(name) = read {
-        if let Some((content, _)) = store.snippets.get(&name) {
-            println!("{}", content);
-        }
-    }
-
-    if let Some(name) = delete {
-        store.snippets.remove(&name);
-        fs::write(path, serde_json::to_string_pretty(&store)).context("Failed to write JSON file")?;
-    }
-
-    Ok(())
+    handle_storage(&mut JsonStorage::open(path)?, name, read, delete, content)
 }
 
 /// Handles snippet operations in SQLite storage.
 pub fn handle_sqlite_storage(
     path: &str,
-    name: Option<String>,
+    name: Option<SnippetName>,
     read: Option<String>,
     delete: Option<String>,
     content: Option<String>,
 ) -> Result<()> {
-    let conn = Connection::open(path).context("Failed to open SQLite DB")?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS snippets (
-            name TEXT PRIMARY KEY,
-            content TEXT NOT NULL,
-            created_at TEXT NOT NULL
-        )",
-        [],
-    )
-    .context("Failed to create table")?;
-    let now = chrono::Utc::now().to_rfc3339();
+    handle_storage(&mut SqliteStorage::open(path)?, name, read, delete, content)
+}
 
+/// Handles snippet operations in any storage backend.
+pub fn handle_storage(
+    storage: &mut dyn SnippetStorage,
+    name: Option<SnippetName>,
+    read: Option<String>,
+    delete: Option<String>,
+    content: Option<String>,
+) -> Result<()> {
     if let Some(name) = name {
-        conn.execute(
-            "INSERT OR REPLACE INTO snippets (name, content, created_at) VALUES (?1, ?2, ?3)",
-            params![name.clone(), content.unwrap_or_default(), now],
-        )
-        .context("Failed to insert snippet")?;
+        storage.save(&name, &Snippet::new(content.unwrap_or_default()))?;
     }
 
     if let Some(name) = read {
-        let mut stmt = conn.prepare("SELECT content FROM snippets WHERE name = ?1")?;
-        let mut rows = stmt.query([name.clone()])?;
-        if let Some(row) = rows.next().transpose()? {
-            let content: String = row.get(0)?;
-            println!("{}", content);
+        match storage.get(&name)? {
+            Some(snippet) => println!("{}", snippet.content),
+            None => return Err(NotFound::new(&storage.names()?, &name).into()),
         }
     }
 
     if let Some(name) = delete {
-        conn.execute("DELETE FROM snippets WHERE name = ?1", [name.clone()])?;
+        storage.remove(&name)?;
     }
 
     Ok(())
 }
+
+/// Outcome of refreshing a downloaded snippet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefreshOutcome {
+    /// The remote content changed and a new revision was recorded.
+    Updated {
+        /// Report of the secrets found in the new content, to be shown to the user.
+        secrets: Option<String>,
+    },
+    /// The remote content is the same as the stored one.
+    Unchanged,
+}
+
+/// Re-downloads the snippet `name` from its recorded source.
+///
+/// The new content is screened for secrets like any other saved content. A new revision is
+/// recorded only when it differs from the stored one; the cache validators of the source are
+/// updated either way. Snippets stored under a name that is no longer valid must be renamed first.
+pub fn refresh_snippet(
+    storage: &mut dyn SnippetStorage,
+    name: &str,
+    options: &DownloadOptions,
+    secrets: &SecretScanner,
+) -> Result<RefreshOutcome> {
+    let mut snippet = storage
+        .get(name)?
+        .with_context(|| format!("Snippet '{name}' not found"))?;
+    let name = SnippetName::new(name).context("Rename the snippet with `mv` to refresh it")?;
+    let source = snippet
+        .source
+        .clone()
+        .with_context(|| format!("Snippet '{name}' was not downloaded and cannot be refreshed"))?;
+    let Some(download) = fetch_snippet(&source, options)? else {
+        return Ok(RefreshOutcome::Unchanged);
+    };
+    logging::record_bytes(download.content.len());
+    let mut downloaded = Snippet::new(download.content);
+    let report = secrets.screen_and_report(name.as_str(), &mut downloaded)?;
+    if downloaded.content == snippet.content {
+        storage.record_source(name.as_str(), &download.source)?;
+        return Ok(RefreshOutcome::Unchanged);
+    }
+    snippet.update_content(downloaded.content);
+    snippet.source = Some(download.source);
+    storage.save(&name, &snippet)?;
+    Ok(RefreshOutcome::Updated { secrets: report })
+}
@@ -0,0 +1,201 @@
+//! Logging to a rotated log file, written from a background thread, and optionally to stderr.
+//!
+//! Every command runs in a `command` span (see [`in_command_span`]) carrying the operation,
+//! snippet name, storage backend and content size, so that JSON logs can be queried by field.
+
+use anyhow::{Context, Result};
+use std::{io, path::Path, time::Instant};
+use tracing::{Span, Subscriber, error, field, info, info_span};
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{
+    EnvFilter, Layer, Registry,
+    fmt::{self, MakeWriter},
+    prelude::*,
+    registry::LookupSpan,
+    reload,
+};
+
+use crate::settings::{LogFormat, LogRotation, SnippetsConfig};
+
+/// Handle to the installed logger.
+///
+/// Keep it alive until the end of `main`: dropping it flushes the log lines still queued for writing.
+pub struct Logging {
+    /// Handle to change the log filter while running.
+    pub filter: reload::Handle<EnvFilter, Registry>,
+    _guard: WorkerGuard,
+}
+
+/// Installs the global logger described by `config`.
+///
+/// The log file is appended to rather than truncated. With a rotation period other than
+/// [`LogRotation::Never`], the date is inserted into the file name, e.g. `snippets.2024-01-31.log`,
+/// and only the newest `log_max_files` files are kept. Files are not rotated by size.
+pub fn init_logging(config: &SnippetsConfig) -> Result<Logging> {
+    let appender = file_appender(&config.log_path, config.log_rotation, config.log_max_files)?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+    let (filter, filter_handle) = reload::Layer::new(EnvFilter::try_new(&config.log_level)?);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(file_layer(config.log_format, writer))
+        .with(config.log_stderr.then(|| fmt::layer().with_writer(io::stderr)))
+        .init();
+    Ok(Logging {
+        filter: filter_handle,
+        _guard: guard,
+    })
+}
+
+/// Formats events for the log file in `format`, writing them with `writer`.
+fn file_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Json => fmt::layer().json().with_writer(writer).boxed(),
+        LogFormat::Text => fmt::layer().with_ansi(false).with_writer(writer).boxed(),
+    }
+}
+
+/// Runs `command` in a `command` span and logs how long it took and whether it failed.
+///
+/// The span has an empty `bytes` field that the command fills in with [`record_bytes`].
+pub fn in_command_span<T>(
+    operation: &str,
+    snippet: Option<&str>,
+    backend: &str,
+    command: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let span = info_span!("command", operation, snippet, backend, bytes = field::Empty);
+    let _entered = span.enter();
+    let started = Instant::now();
+    let result = command();
+    let duration_ms = elapsed_ms(started);
+    match &result {
+        Ok(_) => info!(duration_ms, "Command finished"),
+        Err(err) => error!(duration_ms, error = %format!("{err:#}"), "Command failed"),
+    }
+    result
+}
+
+/// Milliseconds since `started`, with microsecond precision.
+pub fn elapsed_ms(started: Instant) -> f64 {
+    started.elapsed().as_micros() as f64 / 1000.0
+}
+
+/// Records the size of the snippet content handled by the current command.
+pub fn record_bytes(bytes: usize) {
+    Span::current().record("bytes", bytes);
+}
+
+fn file_appender(path: &Path, rotation: LogRotation, max_files: usize) -> Result<RollingFileAppender> {
+    let rotation = match rotation {
+        LogRotation::Never => Rotation::NEVER,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Weekly => Rotation::WEEKLY,
+    };
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut builder = RollingFileAppender::builder().rotation(rotation);
+    if let Some(stem) = path.file_stem() {
+        builder = builder.filename_prefix(stem.to_string_lossy());
+    }
+    if let Some(extension) = path.extension() {
+        builder = builder.filename_suffix(extension.to_string_lossy());
+    }
+    if max_files > 0 {
+        builder = builder.max_log_files(max_files);
+    }
+    builder
+        .build(dir)
+        .with_context(|| format!("Failed to open log file {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use serde_json::Value;
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    /// Log output captured in memory.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs `body` with the log file layer in `format` installed, returning the log lines.
+    fn capture_logs(format: LogFormat, body: impl FnOnce()) -> Vec<String> {
+        let capture = Capture::default();
+        let writer = capture.clone();
+        let subscriber = tracing_subscriber::registry().with(file_layer(format, move || writer.clone()));
+        tracing::subscriber::with_default(subscriber, body);
+        let output = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        output.lines().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn json_lines_carry_the_command_span_fields() {
+        let lines = capture_logs(LogFormat::Json, || {
+            in_command_span("save", Some("rust/let-else"), "sqlite", || {
+                record_bytes(42);
+                Ok(())
+            })
+            .unwrap();
+        });
+        let [line] = lines.as_slice() else {
+            panic!("expected one line, got {lines:?}");
+        };
+        let event: Value = serde_json::from_str(line).unwrap();
+        assert_eq!(event["level"], "INFO");
+        assert_eq!(event["fields"]["message"], "Command finished");
+        assert!(event["fields"]["duration_ms"].is_f64());
+        let span = &event["span"];
+        assert_eq!(span["name"], "command");
+        assert_eq!(span["operation"], "save");
+        assert_eq!(span["snippet"], "rust/let-else");
+        assert_eq!(span["backend"], "sqlite");
+        assert_eq!(span["bytes"], 42);
+    }
+
+    #[test]
+    fn failed_commands_are_logged_with_their_error() {
+        let mut result = Ok(());
+        let lines = capture_logs(LogFormat::Text, || {
+            result = in_command_span("rm", None, "json", || Err(anyhow!("disk full").context("Failed to save")));
+        });
+        assert_eq!(result.unwrap_err().to_string(), "Failed to save");
+        let [line] = lines.as_slice() else {
+            panic!("expected one line, got {lines:?}");
+        };
+        assert!(line.contains(" ERROR command{operation=\"rm\" backend=\"json\"}"), "{line}");
+        assert!(line.contains("Command failed"), "{line}");
+        assert!(line.contains("error=Failed to save: disk full"), "{line}");
+    }
+
+    #[test]
+    fn bytes_are_only_recorded_inside_a_command() {
+        let lines = capture_logs(LogFormat::Json, || {
+            record_bytes(7);
+            info!("outside");
+        });
+        let event: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(event["fields"]["message"], "outside");
+        assert!(event.get("span").is_none());
+    }
+}
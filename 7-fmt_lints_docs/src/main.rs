@@ -1,47 +1,1160 @@
 //! Main entry point for the snippets-app.
 
-use anyhow::Result;
-use clap::Parser;
-use std::env;
-use snippets_app::{handle_json_storage, handle_sqlite_storage, read_snippet_from_stdin};
+use anyhow::{Context, Result, bail};
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap_complete::{ArgValueCompleter, CompleteEnv, Shell, env::Shells};
+use std::{
+    env, fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use chrono::{DateTime, Utc};
+use snippets_app::{
+    RefreshOutcome, Snippet, SnippetStorage, SnippetStore, SourceKind,
+    audit::{AuditFilter, parse_time},
+    completion::{COMPLETE_VAR, complete_names, complete_trashed_names},
+    crypto::{EncryptionScope, NEW_PASSPHRASE_VAR, PASSPHRASE_VAR, PassphraseSource},
+    name::{NotFound, SnippetName, browse, move_target, resolve_name, resolve_prefix},
+    picker::pick,
+    reference::{environment_help, stale_reference, write_reference},
+    tui,
+    usage::{Usage, UsageOrder, parse_cutoff, sort_usage},
+    download::{DownloadOptions, download_snippet, file_url},
+    editor::edit_in_editor,
+    logging::{in_command_span, init_logging, record_bytes},
+    metadata::{language_for_file, metadata_entries, parse_attribute, resolve_author, set_metadata},
+    metrics::store_stats,
+    open_storage, read_snippet_from_stdin, refresh_snippet,
+    reload::{ConfigChange, LiveConfig, watch_config},
+    resolve::parse_line_range,
+    secrets::SecretScanner,
+    select::{NamePattern, Selector},
+    server::serve,
+    settings::{LoadedConfig, LogFormat, LogRotation, load_config},
+};
+use tracing::{error, info, warn};
+use tracing_subscriber::{EnvFilter, Registry, reload};
 
 /// CLI arguments for the snippets-app.
 #[derive(Parser)]
+#[command(about = "Save, find and share code snippets", after_long_help = environment_help())]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Create a snippet with this name from stdin or `--download`, e.g. `rust/patterns/let-else`.
     #[arg(long)]
-    pub name: Option<String>,
-    #[arg(long)]
+    pub name: Option<SnippetName>,
+    /// Print the snippet with this name.
+    #[arg(long, add = ArgValueCompleter::new(complete_names))]
     pub read: Option<String>,
-    #[arg(long)]
+    /// Move the snippet with this name to the trash, see `trash` and `restore`.
+    #[arg(long, add = ArgValueCompleter::new(complete_names))]
     pub delete: Option<String>,
-    #[arg(long)]
+    /// Download the snippet content from this URL instead of reading stdin.
+    #[arg(long, requires = "name", conflicts_with = "file")]
     pub download: Option<String>,
+    /// Read the snippet content from this file instead of reading stdin.
+    #[arg(long, requires = "name")]
+    pub file: Option<PathBuf>,
+    /// Only take this line range of `--file`, e.g. `10:40`.
+    #[arg(long, requires = "file", value_parser = parse_lines)]
+    pub lines: Option<(usize, usize)>,
+    /// Storage to use, e.g. `JSON:snippets.json` or `SQLITE:snippets.sqlite` [config: storage].
+    #[arg(long, global = true)]
+    pub storage: Option<String>,
+    /// File to write the log to [config: log_path].
+    #[arg(long, global = true)]
+    pub log_path: Option<PathBuf>,
+    /// Log level or filter directive [config: log_level].
+    #[arg(long, global = true)]
+    pub log_level: Option<String>,
+    /// How often to start a new log file; rotation is by time only, not by size [config: log_rotation].
+    #[arg(long, global = true)]
+    pub log_rotation: Option<LogRotation>,
+    /// Number of rotated log files to keep, 0 to keep all [config: log_max_files].
+    #[arg(long, global = true)]
+    pub log_max_files: Option<usize>,
+    /// Also write the log to stderr [config: log_stderr].
+    #[arg(long, global = true)]
+    pub log_stderr: bool,
+    /// Format of the log file [config: log_format].
+    #[arg(long, global = true)]
+    pub log_format: Option<LogFormat>,
+    /// Connect timeout for `--download`, in seconds [config: download.connect_timeout].
+    #[arg(long, global = true)]
+    pub connect_timeout: Option<u64>,
+    /// Timeout for the whole `--download` request, body included, in seconds [config: download.read_timeout].
+    #[arg(long, global = true)]
+    pub read_timeout: Option<u64>,
+    /// Maximum size of a downloaded snippet, in bytes [config: download.max_size].
+    #[arg(long, global = true)]
+    pub max_size: Option<u64>,
+    /// Save downloaded content even if it does not look like text [config: download.allow_binary].
+    #[arg(long, global = true)]
+    pub allow_binary: bool,
+    /// Find snippets by name regardless of case if there is no exact match [config: names.ignore_case].
+    #[arg(long, global = true)]
+    pub ignore_case: bool,
+    /// Do not count reads or record their time, e.g. for a store on read-only media [config: usage.track_reads].
+    #[arg(long, global = true)]
+    pub no_track_reads: bool,
+    /// Save the snippet even if it looks like it contains secrets.
+    #[arg(long, requires = "name")]
+    pub allow_secrets: bool,
+    /// Encrypt the content of the snippet, which needs an encrypted store.
+    ///
+    /// An existing private snippet stays private unless `--public` is given.
+    #[arg(long, requires = "name")]
+    pub private: bool,
+    /// Store the content of an existing private snippet unencrypted.
+    #[arg(long, requires = "name", conflicts_with = "private")]
+    pub public: bool,
+    /// Tag the snippet; can be repeated.
+    #[arg(long = "tag", value_name = "TAG", requires = "name")]
+    pub tags: Vec<String>,
+    /// One-line description of the snippet.
+    #[arg(long, requires = "name")]
+    pub description: Option<String>,
+    /// Language of the snippet; guessed from the file or snippet name if not given.
+    #[arg(long, requires = "name")]
+    pub language: Option<String>,
+    /// Author of the snippet, by default the git user or the login name [config: author].
+    #[arg(long, requires = "name")]
+    pub author: Option<String>,
+    /// Set a free-form attribute of the snippet; can be repeated.
+    #[arg(long = "attr", value_name = "KEY=VALUE", requires = "name", value_parser = parse_attr)]
+    pub attributes: Vec<(String, String)>,
+    /// Write the snippet content in $VISUAL or $EDITOR instead of reading stdin.
+    #[arg(long, requires = "name", conflicts_with_all = ["download", "file"])]
+    pub editor: bool,
+}
+
+/// Subcommands of the snippets-app.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Create the store, optionally encrypting it with a passphrase from SNIPPETS_APP_NEW_PASSPHRASE or the terminal.
+    Init {
+        /// Encrypt the content of all snippets, or only that of snippets saved with `--private`.
+        #[arg(long, num_args = 0..=1, default_missing_value = "all")]
+        encrypt: Option<EncryptionScope>,
+    },
+    /// Re-encrypt the store with a new passphrase from SNIPPETS_APP_NEW_PASSPHRASE or the terminal.
+    Rekey,
+    /// Print a snippet, accepting a unique prefix of its name or picking it interactively.
+    Show {
+        /// Name or unique name prefix of the snippet; without it, a fuzzy picker is opened on a terminal.
+        #[arg(add = ArgValueCompleter::new(complete_names))]
+        name: Option<String>,
+        /// Print the metadata of the snippet instead of its content.
+        #[arg(long)]
+        meta: bool,
+    },
+    /// Set or unset metadata of a snippet.
+    Meta {
+        #[command(subcommand)]
+        command: MetaCommand,
+    },
+    /// Browse, edit, tag, rename and delete snippets in a full-screen terminal UI.
+    Tui,
+    /// List snippet names, collapsing the namespaces below the prefix like a directory listing.
+    List {
+        /// Only list names starting with this prefix, e.g. `rust/` for the `rust` namespace.
+        #[arg(add = ArgValueCompleter::new(complete_names))]
+        prefix: Option<String>,
+        /// List every name below the prefix instead of collapsing namespaces.
+        #[arg(long, short)]
+        recursive: bool,
+        /// List every name below the prefix by usage, with the last read time or the read count.
+        #[arg(long, value_enum)]
+        sort: Option<UsageOrder>,
+    },
+    /// Move snippets that were not read, changed or created for a while into an archive namespace.
+    Prune {
+        /// Age such as `180d`, `12w` or `36h`, or a date as `YYYY-MM-DD` or RFC 3339.
+        #[arg(long, value_name = "AGE_OR_DATE")]
+        unused_since: String,
+        /// Namespace to move the snippets into; snippets already in it are kept.
+        #[arg(long, default_value = "archive")]
+        archive: String,
+        /// Only print the snippets that would be archived.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Move the selected snippets to the trash.
+    Rm {
+        #[command(flatten)]
+        selection: Selection,
+    },
+    /// Add tags to or remove tags from the selected snippets.
+    Tag {
+        #[command(flatten)]
+        selection: Selection,
+        /// Tag to add; can be repeated.
+        #[arg(long, value_name = "TAG", required_unless_present = "remove")]
+        add: Vec<String>,
+        /// Tag to remove; can be repeated.
+        #[arg(long, value_name = "TAG")]
+        remove: Vec<String>,
+    },
+    /// Write the selected snippets to a new JSON file that can be opened as a `JSON:` store.
+    ///
+    /// The file is not encrypted, so a selection with private snippets is refused.
+    Export {
+        #[command(flatten)]
+        selection: Selection,
+        /// File to write; it must not exist yet.
+        #[arg(long, short, value_name = "FILE")]
+        output: PathBuf,
+    },
+    /// List or empty the trash of deleted snippets.
+    Trash {
+        #[command(subcommand)]
+        command: TrashCommand,
+    },
+    /// Move a deleted snippet out of the trash.
+    Restore {
+        /// Name of the snippet in the trash.
+        #[arg(add = ArgValueCompleter::new(complete_trashed_names))]
+        name: String,
+    },
+    /// Rename a snippet or move it to another namespace.
+    Mv {
+        /// Current name of the snippet.
+        #[arg(add = ArgValueCompleter::new(complete_names))]
+        from: String,
+        /// New name, or a namespace ending with `/` to keep the name without its namespace.
+        #[arg(add = ArgValueCompleter::new(complete_names))]
+        to: String,
+    },
+    /// Re-download snippets from the URL or file they were created from.
+    Refresh {
+        /// Name of the snippet to refresh.
+        #[arg(required_unless_present = "all", conflicts_with = "all", add = ArgValueCompleter::new(complete_names))]
+        name: Option<String>,
+        /// Refresh every snippet that has a download source.
+        #[arg(long)]
+        all: bool,
+    },
+    /// Show the audit trail of snippet changes.
+    Audit {
+        /// Only changes of this snippet.
+        #[arg(long, add = ArgValueCompleter::new(complete_names))]
+        name: Option<String>,
+        /// Only changes made by this user.
+        #[arg(long)]
+        user: Option<String>,
+        /// Only changes made at or after this time, as `YYYY-MM-DD` or RFC 3339.
+        #[arg(long, value_parser = parse_audit_time)]
+        since: Option<DateTime<Utc>>,
+        /// Only changes made before this time, as `YYYY-MM-DD` or RFC 3339.
+        #[arg(long, value_parser = parse_audit_time)]
+        until: Option<DateTime<Utc>>,
+    },
+    /// Print the number and size of the snippets, the most used tags and the most read snippets.
+    Stats {
+        /// Number of entries in the top lists.
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// Serve the snippets over HTTP, reloading the configuration when it changes or on SIGHUP.
+    Serve {
+        /// Address to listen on [config: server.listen].
+        #[arg(long)]
+        listen: Option<String>,
+    },
+    /// Print the script that completes commands and snippet names in a shell.
+    ///
+    /// For example, add `source <(snippets-app completions bash)` to `~/.bashrc`, or
+    /// `snippets-app completions fish | source` to `~/.config/fish/config.fish`.
+    Completions {
+        /// Shell to complete in.
+        shell: Shell,
+    },
+    /// Write a man page per command and a markdown reference of the command line.
+    Docs {
+        /// Directory to write `cli.md` and the `man` directory into.
+        #[arg(default_value = "docs")]
+        dir: PathBuf,
+        /// Only check that the reference in the directory is up to date.
+        #[arg(long)]
+        check: bool,
+    },
+    /// Inspect the configuration.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+/// Snippets selected by a batch command; a snippet is selected if it matches all given criteria.
+#[derive(Args)]
+pub struct Selection {
+    /// Names or glob patterns such as `'tmp/*'`; `*` and `?` do not match across `/`, `**` does.
+    #[arg(add = ArgValueCompleter::new(complete_names))]
+    pub patterns: Vec<String>,
+    /// Only snippets with this tag; can be repeated.
+    #[arg(long = "tag", value_name = "TAG")]
+    pub tags: Vec<String>,
+    /// Only snippets whose name, description, tags or content contain every word of this query.
+    #[arg(long, value_name = "QUERY")]
+    pub from_query: Option<String>,
+    /// Only print the selected snippets.
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Do not ask for confirmation.
+    #[arg(long, short)]
+    pub yes: bool,
+}
+
+impl Selection {
+    /// The selector of the given criteria, matching patterns regardless of case if `ignore_case` is set.
+    fn selector(&self, ignore_case: bool) -> Result<Selector> {
+        Ok(Selector {
+            patterns: self
+                .patterns
+                .iter()
+                .map(|pattern| NamePattern::new(pattern, ignore_case))
+                .collect::<Result<_>>()?,
+            tags: self.tags.clone(),
+            query: self.from_query.clone(),
+        })
+    }
+}
+
+/// Subcommands of `trash`.
+#[derive(Subcommand)]
+pub enum TrashCommand {
+    /// List the deleted snippets with the time they were deleted.
+    List,
+    /// Delete the snippets in the trash for good.
+    Empty {
+        /// Only snippets deleted before this age such as `30d`, or before a date as `YYYY-MM-DD` or RFC 3339.
+        #[arg(long, value_name = "AGE_OR_DATE")]
+        older_than: Option<String>,
+    },
+}
+
+/// Subcommands of `meta`.
+#[derive(Subcommand)]
+pub enum MetaCommand {
+    /// Set `description`, `language`, `author`, `tags` (comma-separated) or a free-form attribute.
+    Set {
+        /// Name of the snippet.
+        #[arg(add = ArgValueCompleter::new(complete_names))]
+        name: String,
+        /// Metadata key.
+        key: String,
+        /// New value.
+        value: String,
+    },
+    /// Unset `description`, `language`, `author`, `tags` or a free-form attribute.
+    Unset {
+        /// Name of the snippet.
+        #[arg(add = ArgValueCompleter::new(complete_names))]
+        name: String,
+        /// Metadata key.
+        key: String,
+    },
+}
+
+/// Subcommands of `config`.
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Print the effective configuration.
+    Show {
+        /// Also print where each value came from.
+        #[arg(long)]
+        origin: bool,
+    },
+}
+
+impl Cli {
+    /// Configuration values set on the command line, as the highest-priority config layer.
+    pub fn config_overrides(&self) -> Vec<(&'static str, String)> {
+        let mut overrides = Vec::new();
+        if let Some(storage) = &self.storage {
+            overrides.push(("storage", storage.clone()));
+        }
+        if let Some(author) = &self.author {
+            overrides.push(("author", author.clone()));
+        }
+        if let Some(log_path) = &self.log_path {
+            overrides.push(("log_path", log_path.display().to_string()));
+        }
+        if let Some(log_level) = &self.log_level {
+            overrides.push(("log_level", log_level.clone()));
+        }
+        if let Some(log_rotation) = self.log_rotation {
+            overrides.push(("log_rotation", log_rotation.to_string()));
+        }
+        if let Some(log_max_files) = self.log_max_files {
+            overrides.push(("log_max_files", log_max_files.to_string()));
+        }
+        if self.log_stderr {
+            overrides.push(("log_stderr", true.to_string()));
+        }
+        if let Some(log_format) = self.log_format {
+            overrides.push(("log_format", log_format.to_string()));
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            overrides.push(("download.connect_timeout", connect_timeout.to_string()));
+        }
+        if let Some(read_timeout) = self.read_timeout {
+            overrides.push(("download.read_timeout", read_timeout.to_string()));
+        }
+        if let Some(max_size) = self.max_size {
+            overrides.push(("download.max_size", max_size.to_string()));
+        }
+        if self.allow_binary {
+            overrides.push(("download.allow_binary", true.to_string()));
+        }
+        if self.ignore_case {
+            overrides.push(("names.ignore_case", true.to_string()));
+        }
+        if self.no_track_reads {
+            overrides.push(("usage.track_reads", false.to_string()));
+        }
+        if let Some(Command::Serve { listen: Some(listen) }) = &self.command {
+            overrides.push(("server.listen", listen.clone()));
+        }
+        overrides
+    }
 }
 
 /// Main function
 fn main() -> Result<()> {
-    let log_file = env::var("SNIPPETS_APP_LOG_PATH").unwrap_or_else(|_| "snippets.log".into());
-    let log_level = env::var("SNIPPETS_APP_LOG_LEVEL").unwrap_or_else(|_| "info".into());
-    tracing_subscriber::fmt()
-        .with_env_filter(log_level)
-        .with_writer(std::fs::File::create(&log_file)?)
-        .init();
-
+    CompleteEnv::with_factory(Cli::command).var(COMPLETE_VAR).complete();
     let args = Cli::parse();
-    let storage_env = env::var("SNIPPETS_APP_STORAGE").unwrap_or_else(|_| "JSON:snippets.json".into());
-    let content = if args.download.is_some() {
-        reqwest::blocking::get(args.download.clone().unwrap())?.text()?
+
+    if let Some(Command::Completions { shell }) = &args.command {
+        return write_completions(*shell);
+    }
+
+    if let Some(Command::Docs { dir, check }) = &args.command {
+        return write_docs(dir, *check);
+    }
+
+    let overrides = args.config_overrides();
+    let loaded = load_config(&overrides)?;
+    let config = &loaded.config;
+
+    if let Some(Command::Config {
+        command: ConfigCommand::Show { origin },
+    }) = &args.command
+    {
+        show_config(&loaded, *origin);
+        return Ok(());
+    }
+
+    let logging = init_logging(config)?;
+
+    if let Some(Command::Serve { .. }) = &args.command {
+        let listen = config.server.listen.clone();
+        let live = Arc::new(LiveConfig::new(loaded, overrides));
+        let filter = logging.filter.clone();
+        let _watcher = watch_config(Arc::clone(&live), move |loaded, changes| {
+            apply_reloaded_config(&filter, loaded, changes)
+        })?;
+        return serve(&listen, &live);
+    }
+
+    let mut storage = open_storage(&config.storage, PassphraseSource::from_env(PASSPHRASE_VAR).or_prompt())?;
+    let options = config.download.options();
+    let secrets = config.secrets.scanner()?;
+    let backend = config.storage_backend();
+    let ignore_case = config.names.ignore_case;
+    let track_reads = config.usage.track_reads;
+    let resolve = |storage: &dyn SnippetStorage, name: &str| -> Result<String> {
+        Ok(resolve_name(storage, name, ignore_case)?.unwrap_or_else(|| name.to_owned()))
+    };
+
+    if let Some(Command::Init { encrypt }) = &args.command {
+        return in_command_span("init", None, backend, || {
+            let passphrase = encrypt.map(|_| new_passphrase().get_new()).transpose()?;
+            storage.init(passphrase.as_deref().zip(*encrypt))?;
+            match encrypt {
+                Some(scope) => info!("Store initialized, encrypting {} snippets", scope),
+                None => info!("Store initialized"),
+            }
+            Ok(())
+        });
+    }
+
+    if let Some(Command::Rekey) = &args.command {
+        return in_command_span("rekey", None, backend, || {
+            storage.rekey(&new_passphrase().get_new()?)?;
+            info!("Store re-encrypted with a new passphrase");
+            Ok(())
+        });
+    }
+
+    if let Some(Command::Show { name, meta }) = &args.command {
+        return in_command_span("show", name.as_deref(), backend, || {
+            let name = match name {
+                Some(name) => match resolve_name(storage.as_ref(), name, ignore_case)? {
+                    Some(name) => name,
+                    None => {
+                        let names = storage.names()?;
+                        resolve_prefix(&names, name, ignore_case)?.ok_or_else(|| NotFound::new(&names, name))?
+                    }
+                },
+                None if io::stdin().is_terminal() && io::stderr().is_terminal() => match pick(storage.as_ref())? {
+                    Some(name) => name,
+                    None => return Ok(()),
+                },
+                None => bail!("No snippet name given: pass a name or run `show` in a terminal to pick one"),
+            };
+            let snippet = storage.get(&name)?.ok_or_else(|| NotFound::new(&[], &name))?;
+            if *meta {
+                for (key, value) in metadata_entries(&name, &snippet) {
+                    println!("{key}: {value}");
+                }
+                return Ok(());
+            }
+            record_bytes(snippet.content.len());
+            println!("{}", snippet.content);
+            if track_reads {
+                storage.record_read(&name)?;
+            }
+            Ok(())
+        });
+    }
+
+    if let Some(Command::Meta { command }) = &args.command {
+        let (name, key, value) = match command {
+            MetaCommand::Set { name, key, value } => (name, key, Some(value.as_str())),
+            MetaCommand::Unset { name, key } => (name, key, None),
+        };
+        return in_command_span("meta", Some(name), backend, || {
+            let name = resolve(storage.as_ref(), name)?;
+            let Some(mut snippet) = storage.get(&name)? else {
+                return Err(NotFound::new(&storage.names()?, &name).into());
+            };
+            if set_metadata(&mut snippet, key, value)? {
+                let name = SnippetName::new(name).context("Rename the snippet with `mv` to change its metadata")?;
+                storage.save(&name, &snippet)?;
+                info!("Metadata '{}' of snippet '{}' changed", key, name);
+            }
+            Ok(())
+        });
+    }
+
+    if let Some(Command::Tui) = &args.command {
+        if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+            bail!("The terminal UI needs a terminal");
+        }
+        return in_command_span("tui", None, backend, || {
+            if let Some(name) = tui::run(storage.as_mut(), &secrets)? {
+                let snippet = storage.get(&name)?.ok_or_else(|| NotFound::new(&[], &name))?;
+                record_bytes(snippet.content.len());
+                println!("{}", snippet.content);
+                if track_reads {
+                    storage.record_read(&name)?;
+                }
+            }
+            Ok(())
+        });
+    }
+
+    if let Some(Command::List { prefix, recursive, sort }) = &args.command {
+        return in_command_span("list", None, backend, || {
+            let prefix = prefix.as_deref().unwrap_or_default();
+            if let Some(order) = sort {
+                let names = browse(&storage.names()?, prefix, true, ignore_case);
+                let mut usage: Vec<Usage> =
+                    storage.usage()?.into_iter().filter(|usage| names.contains(&usage.name)).collect();
+                sort_usage(&mut usage, *order);
+                for usage in usage {
+                    match order {
+                        UsageOrder::Recent => {
+                            println!("{:<35}  {}", usage.last_accessed_at.as_deref().unwrap_or("never"), usage.name);
+                        }
+                        UsageOrder::Popular => println!("{:>6}  {}", usage.read_count, usage.name),
+                    }
+                }
+                return Ok(());
+            }
+            for entry in browse(&storage.names()?, prefix, *recursive, ignore_case) {
+                println!("{entry}");
+            }
+            Ok(())
+        });
+    }
+
+    if let Some(Command::Prune {
+        unused_since,
+        archive,
+        dry_run,
+    }) = &args.command
+    {
+        let cutoff = parse_cutoff(unused_since, Utc::now())?;
+        return prune(storage.as_mut(), cutoff, archive, *dry_run, backend);
+    }
+
+    if let Some(Command::Rm { selection }) = &args.command {
+        let question = |count| format!("Move {count} snippets to the trash?");
+        return run_batch(storage.as_mut(), selection, ignore_case, "rm", question, backend, |storage, name| {
+            storage.remove(name)?;
+            info!("Snippet '{}' moved to the trash", name);
+            Ok(())
+        });
+    }
+
+    if let Some(Command::Tag { selection, add, remove }) = &args.command {
+        let question = |count| format!("Change the tags of {count} snippets?");
+        return run_batch(storage.as_mut(), selection, ignore_case, "tag", question, backend, |storage, name| {
+            let Some(mut snippet) = storage.get(name)? else {
+                bail!("Snippet '{name}' not found");
+            };
+            let before = snippet.tags.clone();
+            snippet.tags.retain(|tag| !remove.contains(tag));
+            for tag in add {
+                if !snippet.tags.contains(tag) {
+                    snippet.tags.push(tag.clone());
+                }
+            }
+            if snippet.tags != before {
+                let name = SnippetName::new(name).context("Rename the snippet with `mv` to change its tags")?;
+                storage.save(&name, &snippet)?;
+                info!("Tags of snippet '{}' changed", name);
+            }
+            Ok(())
+        });
+    }
+
+    if let Some(Command::Export { selection, output }) = &args.command {
+        return export(storage.as_mut(), selection, ignore_case, output, backend);
+    }
+
+    if let Some(Command::Trash { command }) = &args.command {
+        return match command {
+            TrashCommand::List => in_command_span("trash", None, backend, || {
+                for trashed in storage.trash()? {
+                    println!("{}  {}", trashed.deleted_at, trashed.name);
+                }
+                Ok(())
+            }),
+            TrashCommand::Empty { older_than } => {
+                let cutoff = older_than.as_deref().map(|age| parse_cutoff(age, Utc::now())).transpose()?;
+                empty_trash(storage.as_mut(), cutoff, backend)
+            }
+        };
+    }
+
+    if let Some(Command::Restore { name }) = &args.command {
+        return in_command_span("restore", Some(name), backend, || {
+            if !storage.restore(name)? {
+                bail!("Snippet '{name}' is not in the trash");
+            }
+            info!("Snippet '{}' restored", name);
+            Ok(())
+        });
+    }
+
+    if let Some(Command::Mv { from, to }) = &args.command {
+        return in_command_span("mv", Some(from), backend, || {
+            let from = resolve(storage.as_ref(), from)?;
+            let to = move_target(&from, to)?;
+            if !storage.rename(&from, &to)? {
+                bail!("Snippet '{from}' not found");
+            }
+            info!("Snippet '{}' renamed to '{}'", from, to);
+            Ok(())
+        });
+    }
+
+    if let Some(Command::Refresh { name, all }) = &args.command {
+        let names = if *all {
+            downloaded_snippets(storage.as_ref())?
+        } else {
+            name.iter().map(|name| resolve(storage.as_ref(), name)).collect::<Result<_>>()?
+        };
+        return refresh(storage.as_mut(), &names, &options, &secrets, backend);
+    }
+
+    if let Some(Command::Audit {
+        name,
+        user,
+        since,
+        until,
+    }) = &args.command
+    {
+        let filter = AuditFilter {
+            name: name.clone(),
+            user: user.clone(),
+            since: *since,
+            until: *until,
+        };
+        return in_command_span("audit", name.as_deref(), backend, || {
+            for entry in storage.audit_log()?.iter().filter(|entry| filter.matches(entry)) {
+                println!("{entry}");
+            }
+            Ok(())
+        });
+    }
+
+    if let Some(Command::Stats { top }) = &args.command {
+        return in_command_span("stats", None, backend, || {
+            let stats = store_stats(storage.as_ref())?;
+            println!("snippets: {}", stats.snippets);
+            println!("bytes: {}", stats.bytes);
+            println!("top tags:");
+            for (tag, count) in stats.top_tags.iter().take(*top) {
+                println!("  {tag}: {count}");
+            }
+            println!("most read:");
+            for (name, reads) in stats.most_read.iter().take(*top) {
+                println!("  {name}: {reads}");
+            }
+            Ok(())
+        });
+    }
+
+    if let Some(name) = &args.name {
+        in_command_span("save", Some(name), backend, || {
+            let acquired = acquire_snippet(&args, name, &options)?;
+            let existing = storage.get(name.as_str())?;
+            let is_update = existing.is_some();
+            let mut snippet = match existing {
+                Some(existing) => update_snippet(existing, acquired),
+                None => acquired,
+            };
+            snippet.private = (snippet.private || args.private) && !args.public;
+            for tag in &args.tags {
+                if !snippet.tags.contains(tag) {
+                    snippet.tags.push(tag.clone());
+                }
+            }
+            if args.description.is_some() {
+                snippet.description = args.description.clone();
+            }
+            if let Some(language) = &args.language {
+                snippet.language = Some(language.to_lowercase());
+            }
+            if snippet.language.is_none() {
+                snippet.language = language_for_file(name.base_name()).map(str::to_owned);
+            }
+            if !is_update || args.author.is_some() {
+                snippet.author = resolve_author(&config.author);
+            }
+            snippet.attributes.extend(args.attributes.iter().cloned());
+            if !args.allow_secrets && let Some(report) = secrets.screen_and_report(name.as_str(), &mut snippet)? {
+                eprintln!("{report}");
+            }
+            record_bytes(snippet.content.len());
+            storage.save(name, &snippet)?;
+            info!("Snippet '{}' saved", name);
+            Ok(())
+        })?;
+    }
+
+    if let Some(name) = &args.read {
+        in_command_span("read", Some(name), backend, || {
+            let name = &resolve(storage.as_ref(), name)?;
+            match storage.get(name)? {
+                Some(snippet) => {
+                    record_bytes(snippet.content.len());
+                    println!("{}", snippet.content);
+                    if track_reads {
+                        storage.record_read(name)?;
+                    }
+                }
+                None => return Err(NotFound::new(&storage.names()?, name).into()),
+            }
+            Ok(())
+        })?;
+    }
+
+    if let Some(name) = &args.delete {
+        in_command_span("delete", Some(name), backend, || {
+            let name = &resolve(storage.as_ref(), name)?;
+            if storage.remove(name)? {
+                info!("Snippet '{}' moved to the trash", name);
+            } else {
+                error!("Snippet '{}' not found", name);
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Prints the effective configuration, optionally with the origin of each value.
+fn show_config(loaded: &LoadedConfig, origin: bool) {
+    let width = loaded.entries.iter().map(|entry| entry.key.len() + entry.value.len()).max().unwrap_or(0) + 3;
+    for entry in &loaded.entries {
+        let line = format!("{} = {}", entry.key, entry.value);
+        if origin {
+            println!("{line:width$}  # {}", entry.origin);
+        } else {
+            println!("{line}");
+        }
+    }
+}
+
+/// Applies the parts of a reloaded configuration that need more than a new snapshot.
+///
+/// The log level is switched in place; the other log settings and the listen address are only read at startup.
+fn apply_reloaded_config(
+    filter: &reload::Handle<EnvFilter, Registry>,
+    loaded: &LoadedConfig,
+    changes: &[ConfigChange],
+) {
+    for change in changes {
+        match change.key {
+            "log_level" => match EnvFilter::try_new(&loaded.config.log_level) {
+                Ok(new_filter) => {
+                    if let Err(err) = filter.reload(new_filter) {
+                        error!("Failed to apply log level '{}': {}", loaded.config.log_level, err);
+                    }
+                }
+                Err(err) => error!("Invalid log level '{}': {}", loaded.config.log_level, err),
+            },
+            "log_path" | "log_rotation" | "log_max_files" | "log_stderr" | "log_format" | "server.listen" => {
+                warn!("Change of {} takes effect after a restart", change.key)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Builds the snippet `name` to save from `--download`, `--file`, `--editor` or stdin.
+///
+/// Only called by commands that create snippets, so reading commands never block on stdin.
+fn acquire_snippet(args: &Cli, name: &SnippetName, options: &DownloadOptions) -> Result<Snippet> {
+    if let Some(url) = &args.download {
+        return Ok(download_snippet(url, options)?.into());
+    }
+    if let Some(path) = &args.file {
+        return Ok(download_snippet(&file_url(path, args.lines)?, options)?.into());
+    }
+    let (content, kind) = if args.editor {
+        (edit_in_editor(name, "")?, SourceKind::Editor)
     } else {
-        read_snippet_from_stdin()?
+        (read_snippet_from_stdin()?, SourceKind::Stdin)
+    };
+    let mut snippet = Snippet::new(content);
+    snippet.source_kind = Some(kind);
+    Ok(snippet)
+}
+
+/// Applies newly acquired content to the `existing` snippet, keeping its metadata, read counts and history.
+///
+/// Only a changed content records a new revision; the source is that of the new content.
+fn update_snippet(mut existing: Snippet, acquired: Snippet) -> Snippet {
+    if existing.content != acquired.content {
+        existing.update_content(acquired.content);
+    }
+    existing.source_kind = acquired.source_kind;
+    existing.source = acquired.source;
+    existing.description = existing.description.or(acquired.description);
+    existing.language = existing.language.or(acquired.language);
+    existing
+}
+
+/// Prints the completion script of `shell`, which calls back into this binary to complete.
+fn write_completions(shell: Shell) -> Result<()> {
+    let shells = Shells::builtins();
+    let Some(completer) = shells.completer(&shell.to_string()) else {
+        bail!("Completions are not supported for {shell}");
     };
+    let name = Cli::command().get_name().to_owned();
+    let exe = env::current_exe()?;
+    completer.write_registration(COMPLETE_VAR, &name, &name, &exe.to_string_lossy(), &mut io::stdout())?;
+    Ok(())
+}
+
+/// Writes the reference of the command line into `dir`, or checks that it is up to date.
+fn write_docs(dir: &Path, check: bool) -> Result<()> {
+    if check {
+        let stale = stale_reference(Cli::command(), dir)?;
+        if !stale.is_empty() {
+            let stale: Vec<_> = stale.iter().map(|path| path.display().to_string()).collect();
+            bail!("Reference in {} is stale: {}; run `docs` to update it", dir.display(), stale.join(", "));
+        }
+        return Ok(());
+    }
+    for path in write_reference(Cli::command(), dir)? {
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+/// Source of the new passphrase of `init --encrypt` and `rekey`.
+fn new_passphrase() -> PassphraseSource {
+    PassphraseSource::from_env(NEW_PASSPHRASE_VAR).or_prompt()
+}
+
+/// Parses the `--attr` argument for clap.
+fn parse_attr(attribute: &str) -> Result<(String, String), String> {
+    parse_attribute(attribute).map_err(|err| err.to_string())
+}
+
+/// Parses the `--lines` argument for clap.
+fn parse_lines(lines: &str) -> Result<(usize, usize), String> {
+    parse_line_range(lines).map_err(|err| err.to_string())
+}
+
+/// Parses the `--since` and `--until` arguments of `audit` for clap.
+fn parse_audit_time(time: &str) -> Result<DateTime<Utc>, String> {
+    parse_time(time).map_err(|err| err.to_string())
+}
+
+/// Names of all snippets that were downloaded and can be refreshed.
+fn downloaded_snippets(storage: &dyn SnippetStorage) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for name in storage.names()? {
+        if storage.get(&name)?.is_some_and(|snippet| snippet.source.is_some()) {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
 
-    if storage_env.starts_with("JSON:") {
-        let path = storage_env.trim_start_matches("JSON:");
-        handle_json_storage(path, args.name, args.read, args.delete, Some(content))?;
-    } else if storage_env.starts_with("SQLITE:") {
-        let path = storage_env.trim_start_matches("SQLITE:");
-        handle_sqlite_storage(path, args.name, args.read, args.delete, Some(content))?;
+/// Moves the snippets last used before `cutoff` into the `archive` namespace.
+///
+/// Continues past snippets that cannot be moved, e.g. because the archive already has one of the
+/// same name, and reports them at the end.
+fn prune(
+    storage: &mut dyn SnippetStorage,
+    cutoff: DateTime<Utc>,
+    archive: &str,
+    dry_run: bool,
+    backend: &str,
+) -> Result<()> {
+    let namespace = format!("{}/", archive.trim_end_matches('/'));
+    let unused: Vec<Usage> = storage
+        .usage()?
+        .into_iter()
+        .filter(|usage| !usage.name.starts_with(&namespace) && usage.is_unused_since(cutoff))
+        .collect();
+    let mut failed = 0;
+    for usage in &unused {
+        let outcome = in_command_span("prune", Some(&usage.name), backend, || {
+            let to = SnippetName::new(format!("{namespace}{}", usage.name))?;
+            if !dry_run {
+                storage.rename(&usage.name, &to)?;
+                info!("Snippet '{}' archived to '{}'", usage.name, to);
+            }
+            Ok(to)
+        });
+        match outcome {
+            Ok(to) => println!("{} -> {to}", usage.name),
+            Err(err) => {
+                error!("Failed to archive snippet '{}': {:#}", usage.name, err);
+                eprintln!("{}: {err:#}", usage.name);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{failed} of {} snippets failed to be archived", unused.len());
     }
+    Ok(())
+}
+
+/// Applies `operation` to every snippet of `selection` in a single batch.
+///
+/// With `--dry-run`, only prints the selected snippets. Otherwise asks `question` for the number of
+/// selected snippets unless `--yes` is given, and prints the changed snippets.
+fn run_batch(
+    storage: &mut dyn SnippetStorage,
+    selection: &Selection,
+    ignore_case: bool,
+    command: &str,
+    question: fn(usize) -> String,
+    backend: &str,
+    mut operation: impl FnMut(&mut dyn SnippetStorage, &str) -> Result<()>,
+) -> Result<()> {
+    let names = selection.selector(ignore_case)?.select(storage)?;
+    if names.is_empty() {
+        eprintln!("No snippets selected");
+        return Ok(());
+    }
+    if selection.dry_run {
+        names.iter().for_each(|name| println!("{name}"));
+        return Ok(());
+    }
+    if !selection.yes && !confirm(&question(names.len()))? {
+        bail!("Cancelled");
+    }
+    in_command_span(command, None, backend, || {
+        storage.batch(&mut |storage| names.iter().try_for_each(|name| operation(storage, name)))
+    })?;
+    names.iter().for_each(|name| println!("{name}"));
+    Ok(())
+}
 
+/// Writes the snippets of `selection` to the new JSON store `output`, see [`Command::Export`].
+fn export(
+    storage: &mut dyn SnippetStorage,
+    selection: &Selection,
+    ignore_case: bool,
+    output: &Path,
+    backend: &str,
+) -> Result<()> {
+    if output.exists() {
+        bail!("{} already exists", output.display());
+    }
+    let mut exported = SnippetStore::default();
+    let question = |count| format!("Export {count} snippets?");
+    run_batch(storage, selection, ignore_case, "export", question, backend, |storage, name| {
+        let Some(snippet) = storage.get(name)? else {
+            bail!("Snippet '{name}' not found");
+        };
+        if snippet.private {
+            bail!("Snippet '{name}' is private and cannot be exported to an unencrypted file");
+        }
+        exported.snippets.insert(name.to_owned(), snippet);
+        Ok(())
+    })?;
+    if exported.snippets.is_empty() {
+        return Ok(());
+    }
+    let file = fs::File::create_new(output).with_context(|| format!("Failed to create {}", output.display()))?;
+    serde_json::to_writer_pretty(file, &exported).with_context(|| format!("Failed to write {}", output.display()))?;
+    info!("Exported {} snippets to {}", exported.snippets.len(), output.display());
     Ok(())
 }
+
+/// Asks `question` on the terminal, returning whether it was answered with yes.
+///
+/// Fails without a terminal, where `--yes` has to be given instead.
+fn confirm(question: &str) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        bail!("Not a terminal to confirm on: pass --yes to apply the batch");
+    }
+    eprint!("{question} [y/N] ");
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).context("Failed to read the answer")?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Deletes the snippets in the trash for good, only those deleted before `cutoff` if given.
+///
+/// Snippets whose deletion time cannot be parsed are only deleted without a cutoff.
+fn empty_trash(storage: &mut dyn SnippetStorage, cutoff: Option<DateTime<Utc>>, backend: &str) -> Result<()> {
+    let expired: Vec<String> = storage
+        .trash()?
+        .into_iter()
+        .filter(|trashed| cutoff.is_none_or(|cutoff| parse_time(&trashed.deleted_at).is_ok_and(|time| time < cutoff)))
+        .map(|trashed| trashed.name)
+        .collect();
+    in_command_span("trash-empty", None, backend, || {
+        for name in &expired {
+            storage.purge(name)?;
+            println!("{name}");
+        }
+        info!("Deleted {} snippets from the trash", expired.len());
+        Ok(())
+    })
+}
+
+/// Refreshes the given snippets, continuing past failures and reporting them at the end.
+fn refresh(
+    storage: &mut dyn SnippetStorage,
+    names: &[String],
+    options: &DownloadOptions,
+    secrets: &SecretScanner,
+    backend: &str,
+) -> Result<()> {
+    let mut failed = 0;
+    for name in names {
+        let outcome = in_command_span("refresh", Some(name), backend, || {
+            refresh_snippet(storage, name, options, secrets)
+        });
+        match outcome {
+            Ok(RefreshOutcome::Updated { secrets }) => {
+                info!("Snippet '{}' refreshed", name);
+                println!("{name}: updated");
+                if let Some(report) = secrets {
+                    eprintln!("{report}");
+                }
+            }
+            Ok(RefreshOutcome::Unchanged) => println!("{name}: unchanged"),
+            Err(err) => {
+                error!("Failed to refresh snippet '{}': {:#}", name, err);
+                eprintln!("{name}: {err:#}");
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{failed} of {} snippets failed to refresh", names.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use snippets_app::{JsonStorage, SqliteStorage};
+
+    #[test]
+    fn cli_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn updates_keep_metadata_and_history() {
+        let mut existing = Snippet::new("old".into());
+        existing.private = true;
+        existing.tags = vec!["ops".into()];
+        existing.read_count = 3;
+        existing.language = Some("sh".into());
+        let mut acquired = Snippet::new("new".into());
+        acquired.source_kind = Some(SourceKind::Stdin);
+        acquired.description = Some("Backup script".into());
+
+        let updated = update_snippet(existing.clone(), acquired.clone());
+        assert_eq!(updated.content, "new");
+        assert_eq!(updated.revision, 2);
+        assert_eq!((updated.private, updated.read_count), (true, 3));
+        assert_eq!(updated.created_at, existing.created_at);
+        assert_eq!(updated.tags, ["ops"]);
+        assert_eq!(updated.language.as_deref(), Some("sh"));
+        assert_eq!(updated.description.as_deref(), Some("Backup script"));
+        assert_eq!(updated.source_kind, Some(SourceKind::Stdin));
+
+        acquired.content = "old".into();
+        assert_eq!(update_snippet(existing, acquired).revision, 1);
+    }
+
+    #[test]
+    fn exported_snippets_can_be_opened_as_a_store() {
+        let mut storage = SqliteStorage::open(":memory:").unwrap();
+        storage.init(Some(("secret", EncryptionScope::Private))).unwrap();
+        for (name, private) in [("tmp/a", false), ("tmp/b", false), ("keys/deploy", true)] {
+            let mut snippet = Snippet::new(format!("content of {name}"));
+            snippet.private = private;
+            storage.save(&name.parse().unwrap(), &snippet).unwrap();
+        }
+        let output = env::temp_dir().join(format!("snippets-export-{}.json", std::process::id()));
+        let _ = fs::remove_file(&output);
+        let selection = |patterns: &[&str]| Selection {
+            patterns: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+            tags: Vec::new(),
+            from_query: None,
+            dry_run: false,
+            yes: true,
+        };
+
+        assert!(export(&mut storage, &selection(&["**"]), false, &output, "SQLITE").is_err());
+        assert!(!output.exists(), "a refused batch must not write the file");
+        export(&mut storage, &selection(&["tmp/*"]), false, &output, "SQLITE").unwrap();
+        let exported = JsonStorage::open(&output).unwrap();
+        assert_eq!(exported.names().unwrap(), ["tmp/a", "tmp/b"]);
+        assert_eq!(exported.get("tmp/b").unwrap().unwrap().content, "content of tmp/b");
+        assert!(export(&mut storage, &selection(&["tmp/*"]), false, &output, "SQLITE").is_err());
+        let _ = fs::remove_file(output);
+    }
+
+    #[test]
+    fn committed_reference_is_up_to_date() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("docs");
+        let stale = stale_reference(Cli::command(), &dir).unwrap();
+        assert!(stale.is_empty(), "stale reference files {stale:?}: run `cargo run -- docs` to update them");
+    }
+}
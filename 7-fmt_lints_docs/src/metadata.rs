@@ -0,0 +1,188 @@
+//! Descriptive snippet metadata: languages and descriptions derived from file names, authors,
+//! and the keys edited by `meta set` and `meta unset`.
+
+use anyhow::{Result, bail};
+use std::{env, path::Path, process::Command};
+
+use crate::storage::Snippet;
+
+/// Keys of `meta set` stored in fields of their own rather than as attributes.
+pub const FIELDS: &[&str] = &["description", "language", "author", "tags"];
+
+/// Keys shown by `show --meta` that cannot be set, so attributes cannot shadow them.
+const READ_ONLY: &[&str] = &["name", "source", "url", "created", "updated", "revision", "reads", "private"];
+
+/// Guesses the language of a file from its name.
+pub fn language_for_file(file_name: &str) -> Option<&'static str> {
+    let path = Path::new(file_name);
+    let name = path.file_name()?.to_str()?;
+    match name {
+        "Dockerfile" => return Some("dockerfile"),
+        "Makefile" | "makefile" => return Some("make"),
+        _ => {}
+    }
+    let language = match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "ts" => "typescript",
+        "go" => "go",
+        "c" | "h" => "c",
+        "cpp" | "cc" | "cxx" | "hpp" => "cpp",
+        "cs" => "csharp",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "swift" => "swift",
+        "rb" => "ruby",
+        "php" => "php",
+        "lua" => "lua",
+        "hs" => "haskell",
+        "ex" | "exs" => "elixir",
+        "scala" => "scala",
+        "sh" | "bash" => "shell",
+        "zsh" => "zsh",
+        "ps1" => "powershell",
+        "sql" => "sql",
+        "html" | "htm" => "html",
+        "css" => "css",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        "json" => "json",
+        "xml" => "xml",
+        "md" => "markdown",
+        _ => return None,
+    };
+    Some(language)
+}
+
+/// Builds a one-line description from a file name and an optional 1-based line range.
+pub fn describe_file(file_name: &str, lines: Option<(usize, usize)>) -> String {
+    match lines {
+        Some((start, end)) if start == end => format!("{file_name}, line {start}"),
+        Some((start, end)) => format!("{file_name}, lines {start}-{end}"),
+        None => file_name.to_owned(),
+    }
+}
+
+/// Author of new snippets: `configured` if not empty, else the git user, else the login name.
+pub fn resolve_author(configured: &str) -> Option<String> {
+    if !configured.is_empty() {
+        return Some(configured.to_owned());
+    }
+    if let Some(name) = git_config("user.name") {
+        return Some(match git_config("user.email") {
+            Some(email) => format!("{name} <{email}>"),
+            None => name,
+        });
+    }
+    ["USER", "LOGNAME", "USERNAME"]
+        .into_iter()
+        .find_map(|var| env::var(var).ok().filter(|user| !user.is_empty()))
+}
+
+fn git_config(key: &str) -> Option<String> {
+    let output = Command::new("git").args(["config", "--get", key]).output().ok()?;
+    let value = String::from_utf8(output.stdout).ok()?.trim().to_owned();
+    (output.status.success() && !value.is_empty()).then_some(value)
+}
+
+/// Parses a `KEY=VALUE` attribute as given to `--attr`.
+pub fn parse_attribute(attribute: &str) -> Result<(String, String)> {
+    let Some((key, value)) = attribute.split_once('=') else {
+        bail!("Attribute '{attribute}' must have the form KEY=VALUE");
+    };
+    validate_attribute_key(key)?;
+    Ok((key.to_owned(), value.to_owned()))
+}
+
+/// Checks the key of a free-form attribute.
+fn validate_attribute_key(key: &str) -> Result<()> {
+    if key.is_empty() || key.contains('=') || key.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        bail!("Attribute key '{}' must be non-empty without '=' or whitespace", key.escape_debug());
+    }
+    if FIELDS.contains(&key) || READ_ONLY.contains(&key) {
+        bail!("'{key}' is not a free-form attribute");
+    }
+    Ok(())
+}
+
+/// Sets the metadata `key` of `snippet` to `value`, or unsets it if `value` is `None`.
+///
+/// The keys in [`FIELDS`] set the fields of the same name, `tags` taking a comma-separated list;
+/// any other key is a free-form attribute. Returns whether the snippet changed.
+pub fn set_metadata(snippet: &mut Snippet, key: &str, value: Option<&str>) -> Result<bool> {
+    let value = value.map(str::trim).filter(|value| !value.is_empty()).map(str::to_owned);
+    let changed = match key {
+        "description" => replace(&mut snippet.description, value),
+        "language" => replace(&mut snippet.language, value.map(|language| language.to_lowercase())),
+        "author" => replace(&mut snippet.author, value),
+        "tags" => {
+            let tags = value.map_or_else(Vec::new, |tags| {
+                tags.split(',').map(str::trim).filter(|tag| !tag.is_empty()).map(str::to_owned).collect()
+            });
+            std::mem::replace(&mut snippet.tags, tags) != snippet.tags
+        }
+        _ if READ_ONLY.contains(&key) => bail!("'{key}' cannot be changed with `meta`"),
+        _ => {
+            validate_attribute_key(key)?;
+            match value {
+                Some(value) => snippet.attributes.insert(key.to_owned(), value.clone()) != Some(value),
+                None => snippet.attributes.remove(key).is_some(),
+            }
+        }
+    };
+    Ok(changed)
+}
+
+fn replace(field: &mut Option<String>, value: Option<String>) -> bool {
+    std::mem::replace(field, value) != *field
+}
+
+/// The metadata of the snippet `name` as key/value pairs, as printed by `show --meta`.
+///
+/// Unset fields are left out; attributes follow the fields in key order.
+pub fn metadata_entries(name: &str, snippet: &Snippet) -> Vec<(String, String)> {
+    let mut entries = vec![("name".to_owned(), name.to_owned())];
+    let mut push = |key: &str, value: Option<String>| {
+        if let Some(value) = value {
+            entries.push((key.to_owned(), value));
+        }
+    };
+    push("description", snippet.description.clone());
+    push("language", snippet.language.clone());
+    push("author", snippet.author.clone());
+    push("source", snippet.source_kind.map(|kind| kind.to_string()));
+    push("url", snippet.source.as_ref().map(|source| source.url.clone()));
+    push("tags", (!snippet.tags.is_empty()).then(|| snippet.tags.join(", ")));
+    push("private", snippet.private.then(|| "yes".to_owned()));
+    push("created", Some(snippet.created_at.clone()));
+    push("updated", snippet.updated_at.clone());
+    push("revision", Some(snippet.revision.to_string()));
+    push("reads", Some(snippet.read_count.to_string()));
+    entries.extend(snippet.attributes.iter().map(|(key, value)| (key.clone(), value.clone())));
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meta_sets_fields_and_attributes() {
+        let mut snippet = Snippet::new("code".into());
+        assert!(set_metadata(&mut snippet, "language", Some("Rust")).unwrap());
+        assert!(!set_metadata(&mut snippet, "language", Some("rust")).unwrap());
+        assert!(set_metadata(&mut snippet, "tags", Some("cli, async,")).unwrap());
+        assert!(set_metadata(&mut snippet, "license", Some("MIT")).unwrap());
+        assert_eq!(snippet.language.as_deref(), Some("rust"));
+        assert_eq!(snippet.tags, ["cli", "async"]);
+        assert_eq!(snippet.attributes["license"], "MIT");
+
+        assert!(set_metadata(&mut snippet, "license", None).unwrap());
+        assert!(!set_metadata(&mut snippet, "license", None).unwrap());
+        assert!(set_metadata(&mut snippet, "revision", Some("3")).is_err());
+        assert!(set_metadata(&mut snippet, "a key", Some("x")).is_err());
+        assert!(parse_attribute("tags=x").is_err());
+        assert_eq!(parse_attribute("ticket=ABC-1=2").unwrap(), ("ticket".into(), "ABC-1=2".into()));
+    }
+}
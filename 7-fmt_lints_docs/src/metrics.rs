@@ -0,0 +1,247 @@
+//! Operation metrics of the storage backends and statistics of the store.
+//!
+//! [`MeteredStorage`] wraps any backend and records the number, errors and latency of its
+//! operations into shared [`Metrics`], which `serve` exposes in the Prometheus text format.
+//!
+//! Operation metrics are only recorded by `serve` with `server.metrics` enabled: the other
+//! commands run a single operation and exit, so their counters would never be exported. They
+//! log the duration of every command instead, see [`crate::logging`]. The `stats` command only
+//! uses [`store_stats`], which needs no metrics.
+
+use anyhow::Result;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::{
+    audit::AuditEntry,
+    crypto::EncryptionScope,
+    name::SnippetName,
+    storage::{Snippet, SnippetStorage, Source, TrashedSnippet},
+    usage::Usage,
+};
+
+/// Counters of a single storage operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OperationStats {
+    /// Number of calls.
+    pub count: u64,
+    /// Number of calls that failed.
+    pub errors: u64,
+    /// Total time spent in the calls, in seconds.
+    pub seconds: f64,
+}
+
+/// Storage operation metrics shared between the requests of a running server.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    operations: Mutex<BTreeMap<&'static str, OperationStats>>,
+}
+
+impl Metrics {
+    /// Runs `operation` and records its duration and outcome.
+    fn measure<T>(&self, name: &'static str, operation: impl FnOnce() -> Result<T>) -> Result<T> {
+        let started = Instant::now();
+        let result = operation();
+        let mut operations = self.operations.lock().unwrap_or_else(|err| err.into_inner());
+        let stats = operations.entry(name).or_default();
+        stats.count += 1;
+        stats.errors += u64::from(result.is_err());
+        stats.seconds += started.elapsed().as_secs_f64();
+        result
+    }
+
+    /// Returns the counters of every operation called so far.
+    pub fn operations(&self) -> BTreeMap<&'static str, OperationStats> {
+        self.operations.lock().unwrap_or_else(|err| err.into_inner()).clone()
+    }
+
+    /// Renders the operation metrics and the size of the store in the Prometheus text format.
+    pub fn render(&self, store: &StoreStats) -> String {
+        let operations = self.operations();
+        let mut out = String::new();
+        let mut family = |name: &str, kind: &str, help: &str, value: &dyn Fn(&OperationStats) -> String| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            for (operation, stats) in &operations {
+                let _ = writeln!(out, "{name}{{operation=\"{operation}\"}} {}", value(stats));
+            }
+        };
+        family(
+            "snippets_storage_operations_total",
+            "counter",
+            "Storage operations performed.",
+            &|stats| stats.count.to_string(),
+        );
+        family(
+            "snippets_storage_errors_total",
+            "counter",
+            "Storage operations that failed.",
+            &|stats| stats.errors.to_string(),
+        );
+        family(
+            "snippets_storage_operation_seconds_total",
+            "counter",
+            "Time spent in storage operations.",
+            &|stats| stats.seconds.to_string(),
+        );
+        let _ = writeln!(out, "# HELP snippets_store_snippets Snippets in the store.");
+        let _ = writeln!(out, "# TYPE snippets_store_snippets gauge");
+        let _ = writeln!(out, "snippets_store_snippets {}", store.snippets);
+        let _ = writeln!(out, "# HELP snippets_store_bytes Total size of the snippet contents.");
+        let _ = writeln!(out, "# TYPE snippets_store_bytes gauge");
+        let _ = writeln!(out, "snippets_store_bytes {}", store.bytes);
+        out
+    }
+}
+
+/// A storage backend whose operations are recorded in [`Metrics`].
+pub struct MeteredStorage {
+    inner: Box<dyn SnippetStorage>,
+    metrics: Arc<Metrics>,
+}
+
+impl MeteredStorage {
+    /// Wraps `inner`, recording its operations into `metrics`.
+    pub fn new(inner: Box<dyn SnippetStorage>, metrics: Arc<Metrics>) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+impl SnippetStorage for MeteredStorage {
+    fn get(&self, name: &str) -> Result<Option<Snippet>> {
+        self.metrics.measure("get", || self.inner.get(name))
+    }
+
+    fn save(&mut self, name: &SnippetName, snippet: &Snippet) -> Result<()> {
+        self.metrics.measure("save", || self.inner.save(name, snippet))
+    }
+
+    fn remove(&mut self, name: &str) -> Result<bool> {
+        self.metrics.measure("remove", || self.inner.remove(name))
+    }
+
+    fn rename(&mut self, from: &str, to: &SnippetName) -> Result<bool> {
+        self.metrics.measure("rename", || self.inner.rename(from, to))
+    }
+
+    fn names(&self) -> Result<Vec<String>> {
+        self.metrics.measure("names", || self.inner.names())
+    }
+
+    fn trash(&self) -> Result<Vec<TrashedSnippet>> {
+        self.metrics.measure("trash", || self.inner.trash())
+    }
+
+    fn restore(&mut self, name: &str) -> Result<bool> {
+        self.metrics.measure("restore", || self.inner.restore(name))
+    }
+
+    fn purge(&mut self, name: &str) -> Result<bool> {
+        self.metrics.measure("purge", || self.inner.purge(name))
+    }
+
+    fn audit_log(&self) -> Result<Vec<AuditEntry>> {
+        self.metrics.measure("audit_log", || self.inner.audit_log())
+    }
+
+    fn record_read(&mut self, name: &str) -> Result<()> {
+        self.metrics.measure("record_read", || self.inner.record_read(name))
+    }
+
+    fn record_source(&mut self, name: &str, source: &Source) -> Result<()> {
+        self.metrics.measure("record_source", || self.inner.record_source(name, source))
+    }
+
+    fn usage(&self) -> Result<Vec<Usage>> {
+        self.metrics.measure("usage", || self.inner.usage())
+    }
+
+    fn init(&mut self, encryption: Option<(&str, EncryptionScope)>) -> Result<()> {
+        self.metrics.measure("init", || self.inner.init(encryption))
+    }
+
+    fn rekey(&mut self, passphrase: &str) -> Result<()> {
+        self.metrics.measure("rekey", || self.inner.rekey(passphrase))
+    }
+
+    fn unlock(&self) -> Result<()> {
+        self.metrics.measure("unlock", || self.inner.unlock())
+    }
+
+    fn batch(&mut self, operations: &mut dyn FnMut(&mut dyn SnippetStorage) -> Result<()>) -> Result<()> {
+        self.metrics.measure("batch", || self.inner.batch(operations))
+    }
+}
+
+/// Summary of the store contents, as printed by `stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    /// Number of snippets.
+    pub snippets: usize,
+    /// Total size of the snippet contents in bytes.
+    pub bytes: usize,
+    /// Tags by number of snippets, most common first.
+    pub top_tags: Vec<(String, usize)>,
+    /// Snippets that were read at least once, most read first.
+    pub most_read: Vec<(String, u64)>,
+}
+
+/// Collects the statistics of every snippet in `storage`.
+pub fn store_stats(storage: &dyn SnippetStorage) -> Result<StoreStats> {
+    let mut stats = StoreStats::default();
+    let mut tags = HashMap::<String, usize>::new();
+    for name in storage.names()? {
+        let Some(snippet) = storage.get(&name)? else {
+            continue;
+        };
+        stats.snippets += 1;
+        stats.bytes += snippet.content.len();
+        for tag in snippet.tags {
+            *tags.entry(tag).or_default() += 1;
+        }
+        if snippet.read_count > 0 {
+            stats.most_read.push((name, snippet.read_count));
+        }
+    }
+    stats.top_tags = tags.into_iter().collect();
+    stats.top_tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    stats.most_read.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::SqliteStorage;
+
+    #[test]
+    fn metered_storage_counts_operations_and_reads() {
+        let metrics = Arc::new(Metrics::default());
+        let mut storage = MeteredStorage::new(Box::new(SqliteStorage::open(":memory:").unwrap()), metrics.clone());
+        let mut snippet = Snippet::new("fn main() {}".into());
+        snippet.tags = vec!["rust".into(), "cli".into()];
+        storage.save(&"a".parse().unwrap(), &snippet).unwrap();
+        snippet.content = "print()".into();
+        snippet.tags = vec!["cli".into()];
+        storage.save(&"b".parse().unwrap(), &snippet).unwrap();
+        storage.record_read("b").unwrap();
+
+        let operations = metrics.operations();
+        assert_eq!(operations["save"].count, 2);
+        assert_eq!(operations["record_read"].errors, 0);
+
+        let stats = store_stats(&storage).unwrap();
+        assert_eq!(stats.snippets, 2);
+        assert_eq!(stats.bytes, 19);
+        assert_eq!(stats.top_tags, [("cli".to_owned(), 2), ("rust".to_owned(), 1)]);
+        assert_eq!(stats.most_read, [("b".to_owned(), 1)]);
+
+        let text = metrics.render(&stats);
+        assert!(text.contains("snippets_storage_operations_total{operation=\"save\"} 2\n"));
+        assert!(text.contains("snippets_store_bytes 19\n"));
+    }
+}
@@ -0,0 +1,298 @@
+//! Validated snippet names with `/`-separated namespaces, e.g. `rust/patterns/let-else`.
+
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt, ops::Deref, str::FromStr};
+
+use crate::storage::SnippetStorage;
+
+/// Separator of the namespaces in a snippet name.
+pub const SEPARATOR: char = '/';
+
+/// Maximum length of a snippet name in bytes.
+pub const MAX_LEN: usize = 255;
+
+/// Minimum Jaro-Winkler similarity of a name to be suggested for a missing one.
+const MIN_SIMILARITY: f64 = 0.75;
+
+/// Maximum number of names suggested for a missing one.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// A snippet name that is safe to store, print and browse.
+///
+/// A name is one or more segments separated by `/`. Segments are non-empty, are not `.` or `..`,
+/// contain no control characters, and separate words with single spaces only, so names cannot
+/// differ by whitespace alone.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SnippetName(String);
+
+impl SnippetName {
+    /// Validates `name`.
+    pub fn new(name: impl Into<String>) -> Result<Self> {
+        let name = name.into();
+        if name.is_empty() {
+            bail!("Snippet name must not be empty");
+        }
+        if name.len() > MAX_LEN {
+            bail!("Snippet name must not be longer than {MAX_LEN} bytes");
+        }
+        if name.chars().any(char::is_control) {
+            bail!("Snippet name '{}' must not contain control characters such as newlines", name.escape_debug());
+        }
+        for segment in name.split(SEPARATOR) {
+            if segment.is_empty() {
+                bail!("Snippet name '{name}' must not start or end with '{SEPARATOR}' or contain empty namespaces");
+            }
+            if segment == "." || segment == ".." {
+                bail!("Snippet name '{name}' must not contain '.' or '..' namespaces");
+            }
+            if segment.split(' ').any(str::is_empty) || segment.chars().any(|c| c.is_whitespace() && c != ' ') {
+                bail!("Snippet name '{name}' must separate words with single spaces only");
+            }
+        }
+        Ok(Self(name))
+    }
+
+    /// The name as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The namespace of the snippet, e.g. `rust/patterns` for `rust/patterns/let-else`.
+    pub fn namespace(&self) -> Option<&str> {
+        self.0.rsplit_once(SEPARATOR).map(|(namespace, _)| namespace)
+    }
+
+    /// The name without its namespace, e.g. `let-else` for `rust/patterns/let-else`.
+    pub fn base_name(&self) -> &str {
+        self.0.rsplit_once(SEPARATOR).map_or(&self.0, |(_, base_name)| base_name)
+    }
+}
+
+impl fmt::Display for SnippetName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Deref for SnippetName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for SnippetName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for SnippetName {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Self::new(name)
+    }
+}
+
+impl TryFrom<String> for SnippetName {
+    type Error = anyhow::Error;
+
+    fn try_from(name: String) -> Result<Self> {
+        Self::new(name)
+    }
+}
+
+impl From<SnippetName> for String {
+    fn from(name: SnippetName) -> Self {
+        name.0
+    }
+}
+
+/// Target of a move: `to` itself, or `to` joined with the base name of `from` if `to` ends with `/`.
+pub fn move_target(from: &str, to: &str) -> Result<SnippetName> {
+    match to.strip_suffix(SEPARATOR) {
+        Some(namespace) => {
+            let base_name = from.rsplit_once(SEPARATOR).map_or(from, |(_, base_name)| base_name);
+            SnippetName::new(format!("{namespace}{SEPARATOR}{base_name}"))
+        }
+        None => SnippetName::new(to),
+    }
+}
+
+/// Finds the stored name matching `name`, comparing case-insensitively if `ignore_case` is set.
+///
+/// An exact match always wins. Fails if several names differ from `name` only by case.
+pub fn resolve_name(storage: &dyn SnippetStorage, name: &str, ignore_case: bool) -> Result<Option<String>> {
+    let names = storage.names()?;
+    if names.iter().any(|stored| stored == name) {
+        return Ok(Some(name.to_owned()));
+    }
+    if !ignore_case {
+        return Ok(None);
+    }
+    let lowercase = name.to_lowercase();
+    let mut matches: Vec<String> = names.into_iter().filter(|stored| stored.to_lowercase() == lowercase).collect();
+    match matches.len() {
+        0 | 1 => Ok(matches.pop()),
+        _ => bail!("Snippet name '{name}' is ambiguous, it matches: {}", matches.join(", ")),
+    }
+}
+
+/// Finds the only stored name starting with `prefix`, for `show` to accept abbreviated names.
+///
+/// Fails if several names start with `prefix`, listing them.
+pub fn resolve_prefix(names: &[String], prefix: &str, ignore_case: bool) -> Result<Option<String>> {
+    let matches: Vec<&String> = names
+        .iter()
+        .filter(|name| strip_prefix(name, prefix, ignore_case).is_some())
+        .collect();
+    match matches.as_slice() {
+        [] => Ok(None),
+        [name] => Ok(Some((*name).clone())),
+        _ => bail!(
+            "Snippet name '{prefix}' is ambiguous, it is a prefix of: {}",
+            matches.iter().map(|name| name.as_str()).collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+/// Returns the stored names closest to the missing `name`, most similar first.
+///
+/// Names are compared case-insensitively, both as a whole and by their base name, so that
+/// `let-else` suggests `rust/patterns/let-else`.
+pub fn suggest(names: &[String], name: &str) -> Vec<String> {
+    let name = name.to_lowercase();
+    let mut scored: Vec<(f64, &String)> = names
+        .iter()
+        .map(|candidate| {
+            let lowercase = candidate.to_lowercase();
+            let base_name = lowercase.rsplit_once(SEPARATOR).map_or(lowercase.as_str(), |(_, base_name)| base_name);
+            let score = strsim::jaro_winkler(&name, &lowercase).max(strsim::jaro_winkler(&name, base_name));
+            (score, candidate)
+        })
+        .filter(|(score, _)| *score >= MIN_SIMILARITY)
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    scored.into_iter().take(MAX_SUGGESTIONS).map(|(_, name)| name.clone()).collect()
+}
+
+/// A snippet that does not exist, with the closest existing names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotFound {
+    /// The missing name.
+    pub name: String,
+    /// Existing names similar to it, most similar first.
+    pub suggestions: Vec<String>,
+}
+
+impl NotFound {
+    /// Describes the missing `name`, suggesting similar ones from `names`.
+    pub fn new(names: &[String], name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            suggestions: suggest(names, name),
+        }
+    }
+}
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Snippet '{}' not found", self.name)?;
+        match self.suggestions.as_slice() {
+            [] => Ok(()),
+            [suggestion] => write!(f, "; did you mean '{suggestion}'?"),
+            suggestions => write!(f, "; did you mean one of: {}?", suggestions.join(", ")),
+        }
+    }
+}
+
+impl Error for NotFound {}
+
+/// Lists the entries of `names` starting with `prefix`, like a directory listing.
+///
+/// Unless `recursive` is set, names in namespaces below the prefix are collapsed into one entry
+/// per namespace, e.g. `rust/patterns/` for `rust/patterns/let-else` when listing `rust/`.
+pub fn browse(names: &[String], prefix: &str, recursive: bool, ignore_case: bool) -> Vec<String> {
+    let mut entries: Vec<String> = Vec::new();
+    for name in names {
+        let Some(rest) = strip_prefix(name, prefix, ignore_case) else {
+            continue;
+        };
+        let entry = match rest.find(SEPARATOR) {
+            Some(index) if !recursive => &name[..name.len() - rest.len() + index + 1],
+            _ => name.as_str(),
+        };
+        if entries.last().is_none_or(|last| last != entry) {
+            entries.push(entry.to_owned());
+        }
+    }
+    entries
+}
+
+/// Strips `prefix` from `name`, folding case with [`str::to_lowercase`] like [`resolve_name`] if `ignore_case` is set.
+///
+/// Lowercasing may change the length of a string, so the matching head of `name` is searched char by char.
+fn strip_prefix<'a>(name: &'a str, prefix: &str, ignore_case: bool) -> Option<&'a str> {
+    if !ignore_case {
+        return name.strip_prefix(prefix);
+    }
+    let prefix = prefix.to_lowercase();
+    name.char_indices()
+        .map(|(index, _)| index)
+        .chain([name.len()])
+        .find(|&end| name[..end].to_lowercase() == prefix)
+        .map(|end| &name[end..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        for name in ["", " a", "a ", "a  b", "a\nb", "a\tb", "/a", "a/", "a//b", "a/../b", "a/ b"] {
+            assert!(SnippetName::new(name).is_err(), "{name:?} should be rejected");
+        }
+        let name = SnippetName::new("rust/patterns/let else").unwrap();
+        assert_eq!(name.namespace(), Some("rust/patterns"));
+        assert_eq!(name.base_name(), "let else");
+        assert_eq!(move_target("old/let-else", "rust/").unwrap().as_str(), "rust/let-else");
+    }
+
+    #[test]
+    fn browsing_collapses_namespaces() {
+        let names: Vec<String> = ["hello", "rust/hello", "rust/patterns/let-else", "rust/patterns/newtype"]
+            .map(str::to_owned)
+            .into();
+        assert_eq!(browse(&names, "", false, false), ["hello", "rust/"]);
+        assert_eq!(browse(&names, "rust/", false, false), ["rust/hello", "rust/patterns/"]);
+        assert_eq!(browse(&names, "RUST/patterns/", true, true), ["rust/patterns/let-else", "rust/patterns/newtype"]);
+        assert!(browse(&names, "RUST/", false, false).is_empty());
+
+        let names: Vec<String> = ["Ärger/notes", "straße/a", "İstanbul/b"].map(str::to_owned).into();
+        assert_eq!(browse(&names, "ärger/", false, true), ["Ärger/notes"]);
+        assert_eq!(browse(&names, "STRAßE/", false, true), ["straße/a"]);
+        assert_eq!(browse(&names, "i̇stanbul/", false, true), ["İstanbul/b"]);
+    }
+
+    #[test]
+    fn missing_names_get_suggestions_and_prefixes_resolve() {
+        let names: Vec<String> = ["hello", "rust/patterns/let-else", "rust/patterns/newtype"].map(str::to_owned).into();
+        assert_eq!(suggest(&names, "helo"), ["hello"]);
+        assert_eq!(suggest(&names, "let_else"), ["rust/patterns/let-else"]);
+        assert!(suggest(&names, "kubernetes").is_empty());
+        assert_eq!(
+            NotFound::new(&names, "hell").to_string(),
+            "Snippet 'hell' not found; did you mean 'hello'?"
+        );
+
+        assert_eq!(resolve_prefix(&names, "he", false).unwrap().as_deref(), Some("hello"));
+        assert_eq!(resolve_prefix(&names, "RUST/PATTERNS/N", true).unwrap().as_deref(), Some("rust/patterns/newtype"));
+        assert!(resolve_prefix(&names, "rust/", false).is_err());
+        assert_eq!(resolve_prefix(&names, "x", false).unwrap(), None);
+    }
+}
@@ -0,0 +1,229 @@
+//! Interactive fuzzy picker of snippet names, used by `show` when no name is given on a terminal.
+//!
+//! Typing filters the names with fuzzy matching; the arrow keys move the selection, Enter picks
+//! the selected snippet and Esc or Ctrl-C cancels. The content of the selected snippet is
+//! previewed below the list. The picker is drawn on stderr, so stdout can still be redirected.
+
+use anyhow::Result;
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use fuzzy_matcher::{FuzzyMatcher, skim::SkimMatcherV2};
+use std::io::{self, Stderr, Write};
+
+use crate::storage::SnippetStorage;
+
+/// Result of a key press in the picker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PickerAction {
+    /// Keep picking.
+    Continue,
+    /// The given name was picked.
+    Pick(String),
+    /// The picker was closed without picking.
+    Cancel,
+}
+
+/// State of the picker, independent of the terminal.
+pub struct Picker {
+    names: Vec<String>,
+    query: String,
+    matches: Vec<String>,
+    selected: usize,
+    matcher: SkimMatcherV2,
+}
+
+impl Picker {
+    /// A picker over `names`, initially matching all of them.
+    pub fn new(names: Vec<String>) -> Self {
+        let mut picker = Self {
+            matches: Vec::new(),
+            names,
+            query: String::new(),
+            selected: 0,
+            matcher: SkimMatcherV2::default().ignore_case(),
+        };
+        picker.filter();
+        picker
+    }
+
+    /// The text typed so far.
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// Names matching the query, best match first.
+    pub fn matches(&self) -> &[String] {
+        &self.matches
+    }
+
+    /// The selected name, if any name matches.
+    pub fn selected(&self) -> Option<&str> {
+        self.matches.get(self.selected).map(String::as_str)
+    }
+
+    /// Position of the selected name in [`matches`](Self::matches).
+    pub fn selected_index(&self) -> usize {
+        self.selected
+    }
+
+    /// Number of names, matching or not.
+    pub fn total(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Replaces the names, keeping the query and the selected name if it still matches.
+    pub fn set_names(&mut self, names: Vec<String>) {
+        let selected = self.selected().map(str::to_owned);
+        self.names = names;
+        self.filter();
+        if let Some(index) = selected.and_then(|selected| self.matches.iter().position(|name| *name == selected)) {
+            self.selected = index;
+        }
+    }
+
+    /// Updates the state for a key press.
+    pub fn handle_key(&mut self, key: KeyEvent) -> PickerAction {
+        match key.code {
+            KeyCode::Esc => return PickerAction::Cancel,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return PickerAction::Cancel,
+            KeyCode::Enter => {
+                return self.selected().map_or(PickerAction::Continue, |name| PickerAction::Pick(name.to_owned()));
+            }
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => self.selected = (self.selected + 1).min(self.matches.len().saturating_sub(1)),
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.filter();
+            }
+            KeyCode::Char(c) if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                self.query.push(c);
+                self.filter();
+            }
+            _ => {}
+        }
+        PickerAction::Continue
+    }
+
+    fn filter(&mut self) {
+        let mut scored: Vec<(i64, &String)> = self
+            .names
+            .iter()
+            .filter_map(|name| Some((self.matcher.fuzzy_match(name, &self.query)?, name)))
+            .collect();
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        self.matches = scored.into_iter().map(|(_, name)| name.clone()).collect();
+        self.selected = 0;
+    }
+}
+
+/// Raw mode on the alternate screen of stderr, restored when dropped, even by an error.
+pub(crate) struct TerminalGuard(pub(crate) Stderr);
+
+impl TerminalGuard {
+    /// Switches the terminal to raw mode on the alternate screen.
+    pub(crate) fn enter() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut stderr = io::stderr();
+        execute!(stderr, EnterAlternateScreen, Hide)?;
+        Ok(Self(stderr))
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(self.0, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Lets the user pick a snippet of `storage` on the terminal, returning `None` if cancelled.
+pub fn pick(storage: &dyn SnippetStorage) -> Result<Option<String>> {
+    storage.unlock()?;
+    let mut picker = Picker::new(storage.names()?);
+    let mut guard = TerminalGuard::enter()?;
+    loop {
+        draw(&mut guard.0, &picker, storage)?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match picker.handle_key(key) {
+            PickerAction::Continue => {}
+            PickerAction::Pick(name) => return Ok(Some(name)),
+            PickerAction::Cancel => return Ok(None),
+        }
+    }
+}
+
+/// Draws the query, the best matches and a preview of the selected snippet.
+fn draw(out: &mut Stderr, picker: &Picker, storage: &dyn SnippetStorage) -> Result<()> {
+    let (width, height) = terminal::size()?;
+    let (width, height) = (usize::from(width), usize::from(height));
+    let list_rows = (height.saturating_sub(2) / 2).max(1);
+    queue!(out, Clear(ClearType::All), MoveTo(0, 0), Print(truncate(&format!("> {}", picker.query()), width)))?;
+
+    let offset = picker.selected.saturating_sub(list_rows - 1);
+    for (row, name) in picker.matches().iter().skip(offset).take(list_rows).enumerate() {
+        queue!(out, MoveTo(0, (row + 1) as u16))?;
+        if offset + row == picker.selected {
+            queue!(out, SetAttribute(Attribute::Reverse), Print(truncate(&format!("> {name}"), width)))?;
+            queue!(out, SetAttribute(Attribute::Reset))?;
+        } else {
+            queue!(out, Print(truncate(&format!("  {name}"), width)))?;
+        }
+    }
+
+    let separator_row = list_rows + 1;
+    let status = format!("{}/{} ", picker.matches().len(), picker.total());
+    queue!(out, MoveTo(0, separator_row as u16), Print(format!("{status:─<width$}")))?;
+    if let Some(name) = picker.selected() {
+        let preview = match storage.get(name) {
+            Ok(Some(snippet)) => snippet.content,
+            Ok(None) => String::new(),
+            Err(err) => format!("{err:#}"),
+        };
+        for (row, line) in preview.lines().take(height.saturating_sub(separator_row + 1)).enumerate() {
+            queue!(out, MoveTo(0, (separator_row + 1 + row) as u16), Print(truncate(line, width)))?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Cuts `line` to `width` characters, replacing tabs so the terminal layout is kept.
+fn truncate(line: &str, width: usize) -> String {
+    line.replace('\t', "    ").chars().filter(|c| !c.is_control()).take(width).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(picker: &mut Picker, code: KeyCode) -> PickerAction {
+        picker.handle_key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn typing_filters_and_enter_picks() {
+        let names = ["hello", "rust/patterns/let-else", "rust/patterns/newtype"].map(str::to_owned).into();
+        let mut picker = Picker::new(names);
+        assert_eq!(picker.matches().len(), 3);
+        for c in "nwt".chars() {
+            press(&mut picker, KeyCode::Char(c));
+        }
+        assert_eq!(picker.matches(), ["rust/patterns/newtype"]);
+        press(&mut picker, KeyCode::Backspace);
+        press(&mut picker, KeyCode::Backspace);
+        press(&mut picker, KeyCode::Down);
+        assert_eq!(picker.matches().len(), 2);
+        assert_eq!(press(&mut picker, KeyCode::Enter), PickerAction::Pick(picker.matches()[1].clone()));
+        assert_eq!(press(&mut picker, KeyCode::Esc), PickerAction::Cancel);
+    }
+}
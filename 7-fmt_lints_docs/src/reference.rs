@@ -0,0 +1,156 @@
+//! Reference documentation generated from the clap definition of the command line.
+//!
+//! The `docs` command writes one man page per command into `man/` and the `--help` of every
+//! command into `cli.md`. The environment variables, which clap does not know about, are listed
+//! in the `--help` of the root command and in the ENVIRONMENT section of its man page.
+
+use anyhow::{Context, Result};
+use clap::Command;
+use clap_mangen::Man;
+use roff::{Roff, bold, roman};
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    crypto::{NEW_PASSPHRASE_VAR, PASSPHRASE_VAR},
+    settings::{DEFAULTS, env_var_name},
+};
+
+/// Name of the markdown reference in the output directory.
+pub const MARKDOWN_FILE: &str = "cli.md";
+
+/// Directory of the man pages in the output directory.
+pub const MAN_DIR: &str = "man";
+
+/// Environment variables read by the snippets-app, with their description.
+pub fn environment() -> Vec<(String, String)> {
+    let mut variables: Vec<(String, String)> = DEFAULTS
+        .iter()
+        .map(|(key, default)| {
+            let description = match *default {
+                "" => format!("Sets the `{key}` configuration value"),
+                default => format!("Sets the `{key}` configuration value [default: {default}]"),
+            };
+            (env_var_name(key), description)
+        })
+        .collect();
+    variables.extend([
+        (PASSPHRASE_VAR.to_owned(), "Passphrase of an encrypted store, asked on the terminal if unset".to_owned()),
+        (
+            NEW_PASSPHRASE_VAR.to_owned(),
+            "New passphrase of `init --encrypt` and `rekey`, asked on the terminal if unset".to_owned(),
+        ),
+        ("VISUAL, EDITOR".to_owned(), "Editor opened by `tui`, `vi` if both are unset".to_owned()),
+    ]);
+    variables
+}
+
+/// The environment variables as a help section, shown after the options by `--help`.
+pub fn environment_help() -> String {
+    let variables = environment();
+    let width = variables.iter().map(|(variable, _)| variable.len()).max().unwrap_or(0);
+    let mut help = String::from("Environment variables:\n");
+    for (variable, description) in variables {
+        let _ = writeln!(help, "  {variable:width$}  {description}");
+    }
+    help
+}
+
+/// Renders the reference of `cmd`: a man page per command and the markdown reference.
+///
+/// Paths are relative to the output directory.
+pub fn reference_files(cmd: Command) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+    let mut cmd = cmd.disable_help_subcommand(true);
+    cmd.build();
+    let mut files = Vec::new();
+    add_man_pages(&cmd, true, &mut files)?;
+    files.push((PathBuf::from(MARKDOWN_FILE), markdown(&cmd).into_bytes()));
+    Ok(files)
+}
+
+/// Writes the reference of `cmd` into `dir`, returning the paths of the written files.
+///
+/// Man pages of commands that no longer exist are removed.
+pub fn write_reference(cmd: Command, dir: &Path) -> Result<Vec<PathBuf>> {
+    let files = reference_files(cmd)?;
+    let man_dir = dir.join(MAN_DIR);
+    fs::create_dir_all(&man_dir).with_context(|| format!("Failed to create {}", man_dir.display()))?;
+    for entry in fs::read_dir(&man_dir)? {
+        let path = entry?.path();
+        if !files.iter().any(|(generated, _)| dir.join(generated) == path) {
+            fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+    }
+    let mut written = Vec::new();
+    for (path, content) in files {
+        let path = dir.join(path);
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Files of the reference in `dir` that are missing, outdated or left over from removed commands.
+pub fn stale_reference(cmd: Command, dir: &Path) -> Result<Vec<PathBuf>> {
+    let files = reference_files(cmd)?;
+    let mut stale: Vec<PathBuf> = files
+        .iter()
+        .filter(|(path, content)| fs::read(dir.join(path)).ok().as_ref() != Some(content))
+        .map(|(path, _)| path.clone())
+        .collect();
+    if let Ok(entries) = fs::read_dir(dir.join(MAN_DIR)) {
+        for entry in entries {
+            let path = Path::new(MAN_DIR).join(entry?.file_name());
+            if !files.iter().any(|(generated, _)| *generated == path) {
+                stale.push(path);
+            }
+        }
+    }
+    stale.sort();
+    Ok(stale)
+}
+
+/// Adds the man page of `cmd` and of its visible subcommands to `files`.
+fn add_man_pages(cmd: &Command, root: bool, files: &mut Vec<(PathBuf, Vec<u8>)>) -> Result<()> {
+    // The environment gets its own section instead of the EXTRA section of `after_long_help`.
+    let man = Man::new(cmd.clone().after_long_help(None::<&str>));
+    let mut page = Vec::new();
+    man.render(&mut page)?;
+    if root {
+        let mut roff = Roff::new();
+        roff.control("SH", ["ENVIRONMENT"]);
+        for (variable, description) in environment() {
+            roff.control("TP", []);
+            roff.text([bold(variable)]);
+            roff.text([roman(description)]);
+        }
+        page.extend(roff.to_roff().into_bytes());
+    }
+    files.push((Path::new(MAN_DIR).join(man.get_filename()), page));
+    for subcommand in cmd.get_subcommands().filter(|subcommand| !subcommand.is_hide_set()) {
+        add_man_pages(subcommand, false, files)?;
+    }
+    Ok(())
+}
+
+/// The `--help` of `cmd` and of its visible subcommands, one section per command.
+fn markdown(cmd: &Command) -> String {
+    let mut markdown = format!(
+        "# Command-line reference\n\nGenerated from the command line definition by `{} docs`, do not edit.\n",
+        cmd.get_name()
+    );
+    add_markdown(cmd, &mut markdown);
+    markdown
+}
+
+fn add_markdown(cmd: &Command, markdown: &mut String) {
+    let name = cmd.get_bin_name().unwrap_or_else(|| cmd.get_name());
+    let help = cmd.clone().render_long_help().to_string();
+    let _ = write!(markdown, "\n## `{name}`\n\n```text\n{}\n```\n", help.trim_end());
+    for subcommand in cmd.get_subcommands().filter(|subcommand| !subcommand.is_hide_set()) {
+        add_markdown(subcommand, markdown);
+    }
+}
@@ -0,0 +1,188 @@
+//! Live reloading of the configuration for long-running commands such as `serve`.
+//!
+//! A reload is triggered by changes to any of the configuration files or, on Unix, by `SIGHUP`.
+//! The new configuration is validated before it replaces the active one; an invalid update is
+//! logged and rejected, and the previous configuration stays in effect.
+
+use anyhow::{Context, Result};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::{Arc, RwLock, mpsc},
+    thread,
+    time::Duration,
+};
+use tracing::{error, info};
+
+use crate::settings::{ConfigEntry, LoadedConfig, config_file_candidates, load_config};
+
+/// Time to wait for further events before reloading, so that one save triggers one reload.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// A configuration value that changed on reload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigChange {
+    /// Dotted configuration key.
+    pub key: &'static str,
+    /// Value before the reload.
+    pub old: String,
+    /// Value after the reload.
+    pub new: String,
+}
+
+impl fmt::Display for ConfigChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.key, self.old, self.new)
+    }
+}
+
+/// Lists the values that differ between two sets of configuration entries.
+pub fn diff_entries(old: &[ConfigEntry], new: &[ConfigEntry]) -> Vec<ConfigChange> {
+    new.iter()
+        .filter_map(|entry| {
+            let old = old.iter().find(|old| old.key == entry.key).map(|old| old.value.as_str());
+            (old != Some(entry.value.as_str())).then(|| ConfigChange {
+                key: entry.key,
+                old: old.unwrap_or_default().to_owned(),
+                new: entry.value.clone(),
+            })
+        })
+        .collect()
+}
+
+/// The active configuration of a running process, replaced as a whole on reload.
+pub struct LiveConfig {
+    cli_overrides: Vec<(&'static str, String)>,
+    current: RwLock<Arc<LoadedConfig>>,
+}
+
+impl LiveConfig {
+    /// Wraps an already loaded configuration; reloads apply the same `cli_overrides`.
+    pub fn new(loaded: LoadedConfig, cli_overrides: Vec<(&'static str, String)>) -> Self {
+        Self {
+            cli_overrides,
+            current: RwLock::new(Arc::new(loaded)),
+        }
+    }
+
+    /// Returns a snapshot of the active configuration.
+    pub fn get(&self) -> Arc<LoadedConfig> {
+        Arc::clone(&self.current.read().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    /// Loads and validates the configuration again and makes it active if it is valid.
+    ///
+    /// Returns the changed values; on error the active configuration is left untouched.
+    pub fn reload(&self) -> Result<Vec<ConfigChange>> {
+        let overrides: Vec<(&str, String)> =
+            self.cli_overrides.iter().map(|(key, value)| (*key, value.clone())).collect();
+        let loaded = Arc::new(load_config(&overrides)?);
+        let mut current = self.current.write().unwrap_or_else(|err| err.into_inner());
+        let changes = diff_entries(&current.entries, &loaded.entries);
+        *current = loaded;
+        Ok(changes)
+    }
+}
+
+/// Keeps the configuration watch running; dropping it stops watching the files.
+pub struct ConfigWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+/// Reloads `live` whenever a configuration file changes or the process receives `SIGHUP`.
+///
+/// `on_reload` is called with the new configuration after every successful reload that changed a value.
+pub fn watch_config(
+    live: Arc<LiveConfig>,
+    on_reload: impl Fn(&LoadedConfig, &[ConfigChange]) + Send + 'static,
+) -> Result<ConfigWatcher> {
+    let (sender, receiver) = mpsc::channel::<()>();
+    let files = config_file_candidates();
+
+    let watched = files.clone();
+    let file_sender = sender.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        // Reading the files on reload produces access events, which must not trigger another reload.
+        if let Ok(event) = event
+            && !matches!(event.kind, EventKind::Access(_))
+            && event.paths.iter().any(|path| watched.contains(path))
+        {
+            let _ = file_sender.send(());
+        }
+    })
+    .context("Failed to start watching configuration files")?;
+    // Editors often replace files instead of writing them in place, so watch the directories.
+    for dir in watched_dirs(&files) {
+        watcher
+            .watch(&dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("Failed to watch {}", dir.display()))?;
+    }
+
+    #[cfg(unix)]
+    {
+        use signal_hook::{consts::SIGHUP, iterator::Signals};
+        let mut signals = Signals::new([SIGHUP]).context("Failed to register SIGHUP handler")?;
+        thread::spawn(move || {
+            for _ in signals.forever() {
+                info!("Received SIGHUP, reloading configuration");
+                if sender.send(()).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    thread::spawn(move || {
+        while receiver.recv().is_ok() {
+            while receiver.recv_timeout(DEBOUNCE).is_ok() {}
+            match live.reload() {
+                Ok(changes) if changes.is_empty() => info!("Configuration reloaded, nothing changed"),
+                Ok(changes) => {
+                    for change in &changes {
+                        info!("Configuration changed: {}", change);
+                    }
+                    on_reload(&live.get(), &changes);
+                }
+                Err(err) => error!("Rejected configuration update, keeping the previous one: {:#}", err),
+            }
+        }
+    });
+
+    Ok(ConfigWatcher { _watcher: watcher })
+}
+
+/// Existing parent directories of `files`, without duplicates.
+fn watched_dirs(files: &[PathBuf]) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = files
+        .iter()
+        .filter_map(|file| file.parent())
+        .filter(|dir| dir.is_dir())
+        .map(PathBuf::from)
+        .collect();
+    dirs.dedup();
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::ConfigOrigin;
+
+    fn entry(key: &'static str, value: &str) -> ConfigEntry {
+        ConfigEntry {
+            key,
+            value: value.into(),
+            origin: ConfigOrigin::Default,
+        }
+    }
+
+    #[test]
+    fn diff_lists_changed_values_only() {
+        let old = [entry("storage", "JSON:a.json"), entry("log_level", "info")];
+        let new = [entry("storage", "JSON:a.json"), entry("log_level", "debug")];
+        let changes = diff_entries(&old, &new);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].to_string(), "log_level: info -> debug");
+    }
+}
@@ -0,0 +1,215 @@
+//! Resolving links to code hosting pages into their raw content.
+
+use anyhow::{Context, Result, bail};
+use reqwest::Url;
+
+/// A download URL resolved to the location of its raw content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedUrl {
+    /// URL serving the raw content.
+    pub raw_url: String,
+    /// 1-based inclusive line range to extract from the content.
+    pub lines: Option<(usize, usize)>,
+    /// Name of the file behind the URL, if it can be told from the URL.
+    pub file_name: Option<String>,
+}
+
+/// Resolves GitHub gist pages, GitHub/GitLab blob URLs and paste-bin links to their raw content.
+///
+/// Line anchors such as `#L10-L20` (GitHub) or `#L10-20` (GitLab) are turned into a line range.
+/// Other URLs are returned unchanged.
+pub fn resolve_url(url: &str) -> Result<ResolvedUrl> {
+    let parsed = Url::parse(url).with_context(|| format!("Invalid URL '{url}'"))?;
+    let host = parsed.host_str().unwrap_or_default();
+    let segments: Vec<&str> = parsed
+        .path_segments()
+        .map(|segments| segments.filter(|segment| !segment.is_empty()).collect())
+        .unwrap_or_default();
+    let file_name = segments
+        .last()
+        .filter(|segment| segment.contains('.'))
+        .map(|segment| (*segment).to_owned());
+
+    if parsed.scheme() == "file" {
+        let mut raw_url = parsed.clone();
+        raw_url.set_fragment(None);
+        return Ok(ResolvedUrl {
+            raw_url: raw_url.into(),
+            lines: line_range(parsed.fragment())?,
+            file_name,
+        });
+    }
+
+    if host.starts_with("gitlab.")
+        && let Some((project, rest)) = gitlab_blob(&segments)
+    {
+        return Ok(ResolvedUrl {
+            raw_url: format!("https://{host}/{}/-/raw/{}", project.join("/"), rest.join("/")),
+            lines: line_range(parsed.fragment())?,
+            file_name,
+        });
+    }
+
+    let gist_file = gist_file_name(parsed.fragment());
+    let gist_raw_path = gist_file.as_ref().map_or_else(String::new, |file| format!("/{file}"));
+    let resolved = match (host, segments.as_slice()) {
+        ("gist.github.com", [user, id]) => ResolvedUrl {
+            raw_url: format!("https://gist.githubusercontent.com/{user}/{id}/raw{gist_raw_path}"),
+            lines: None,
+            file_name: gist_file,
+        },
+        ("gist.github.com", [id]) => ResolvedUrl {
+            raw_url: format!("https://gist.github.com/{id}/raw{gist_raw_path}"),
+            lines: None,
+            file_name: gist_file,
+        },
+        ("github.com", [owner, repo, "blob", rest @ ..]) if !rest.is_empty() => ResolvedUrl {
+            raw_url: format!("https://raw.githubusercontent.com/{owner}/{repo}/{}", rest.join("/")),
+            lines: line_range(parsed.fragment())?,
+            file_name,
+        },
+        ("pastebin.com", [id]) => ResolvedUrl {
+            raw_url: format!("https://pastebin.com/raw/{id}"),
+            lines: None,
+            file_name: None,
+        },
+        ("dpaste.com", [id]) if !id.contains('.') => ResolvedUrl {
+            raw_url: format!("https://dpaste.com/{id}.txt"),
+            lines: None,
+            file_name: None,
+        },
+        _ => ResolvedUrl {
+            raw_url: url.to_owned(),
+            lines: None,
+            file_name,
+        },
+    };
+    Ok(resolved)
+}
+
+/// Recovers the file name from a gist `#file-<name>` anchor, in which GitHub replaces dots with dashes.
+///
+/// The last dash is taken to be the extension dot, so `#file-main-rs` names `main.rs`. The file is then
+/// downloaded by that name, so a wrong guess, e.g. for `a.test.js`, fails instead of downloading another file.
+fn gist_file_name(fragment: Option<&str>) -> Option<String> {
+    let anchor = fragment?.strip_prefix("file-")?;
+    let (stem, extension) = anchor.rsplit_once('-')?;
+    (!stem.is_empty() && !extension.is_empty()).then(|| format!("{stem}.{extension}"))
+}
+
+/// Splits GitLab `<project>/-/blob/<ref>/<path>` segments into project and `<ref>/<path>`.
+fn gitlab_blob<'a>(segments: &'a [&'a str]) -> Option<(&'a [&'a str], &'a [&'a str])> {
+    let position = segments.windows(2).position(|pair| pair == ["-", "blob"])?;
+    let (project, rest) = (&segments[..position], &segments[position + 2..]);
+    (!project.is_empty() && !rest.is_empty()).then_some((project, rest))
+}
+
+/// Parses a `L10`, `L10-L20` or `L10-20` line anchor.
+fn line_range(fragment: Option<&str>) -> Result<Option<(usize, usize)>> {
+    let Some(anchor) = fragment.and_then(|fragment| fragment.strip_prefix('L')) else {
+        return Ok(None);
+    };
+    let (start, end) = match anchor.split_once('-') {
+        Some((start, end)) => (start, end.trim_start_matches('L')),
+        None => (anchor, anchor),
+    };
+    let start: usize = start.parse().with_context(|| format!("Invalid line anchor '#L{anchor}'"))?;
+    let end: usize = end.parse().with_context(|| format!("Invalid line anchor '#L{anchor}'"))?;
+    if start == 0 || end < start {
+        bail!("Invalid line range {start}-{end}");
+    }
+    Ok(Some((start, end)))
+}
+
+/// Parses a `--lines` argument such as `10:40` (or `10` for a single line).
+pub fn parse_line_range(lines: &str) -> Result<(usize, usize)> {
+    let (start, end) = lines.split_once(':').unwrap_or((lines, lines));
+    let start: usize = start.trim().parse().with_context(|| format!("Invalid line range '{lines}'"))?;
+    let end: usize = end.trim().parse().with_context(|| format!("Invalid line range '{lines}'"))?;
+    if start == 0 || end < start {
+        bail!("Invalid line range {start}:{end}");
+    }
+    Ok((start, end))
+}
+
+/// Extracts the 1-based inclusive line range from `content`.
+pub fn extract_lines(content: &str, (start, end): (usize, usize)) -> Result<String> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    if start > lines.len() {
+        bail!("Line {start} is past the end of the content ({} lines)", lines.len());
+    }
+    Ok(lines[start - 1..end.min(lines.len())].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gist_page_resolves_to_raw() {
+        let resolved = resolve_url("https://gist.github.com/TheBestTvarynka/bb2e8fee52abaf3bf1e9b567453d7466").unwrap();
+        assert_eq!(
+            resolved.raw_url,
+            "https://gist.githubusercontent.com/TheBestTvarynka/bb2e8fee52abaf3bf1e9b567453d7466/raw"
+        );
+    }
+
+    #[test]
+    fn gist_anchor_selects_the_file_to_download() {
+        let resolved = resolve_url("https://gist.github.com/user/0123abcd#file-build-script-sh").unwrap();
+        assert_eq!(resolved.raw_url, "https://gist.githubusercontent.com/user/0123abcd/raw/build-script.sh");
+        assert_eq!(resolved.file_name.as_deref(), Some("build-script.sh"));
+        let resolved = resolve_url("https://gist.github.com/0123abcd#file-lib-rs").unwrap();
+        assert_eq!(resolved.raw_url, "https://gist.github.com/0123abcd/raw/lib.rs");
+        assert_eq!(resolved.file_name.as_deref(), Some("lib.rs"));
+        assert_eq!(resolve_url("https://gist.github.com/user/0123abcd").unwrap().file_name, None);
+
+        let resolved = resolve_url("https://gist.githubusercontent.com/user/0123abcd/raw/4567/main.rs").unwrap();
+        assert_eq!(resolved.raw_url, "https://gist.githubusercontent.com/user/0123abcd/raw/4567/main.rs");
+        assert_eq!(resolved.file_name.as_deref(), Some("main.rs"));
+    }
+
+    #[test]
+    fn github_blob_resolves_with_lines() {
+        let resolved = resolve_url("https://github.com/rust-lang/rust/blob/master/src/main.rs#L10-L20").unwrap();
+        assert_eq!(resolved.raw_url, "https://raw.githubusercontent.com/rust-lang/rust/master/src/main.rs");
+        assert_eq!(resolved.lines, Some((10, 20)));
+        assert_eq!(resolved.file_name.as_deref(), Some("main.rs"));
+    }
+
+    #[test]
+    fn gitlab_blob_resolves_with_lines() {
+        let resolved = resolve_url("https://gitlab.com/group/sub/project/-/blob/main/lib.py#L3-5").unwrap();
+        assert_eq!(resolved.raw_url, "https://gitlab.com/group/sub/project/-/raw/main/lib.py");
+        assert_eq!(resolved.lines, Some((3, 5)));
+    }
+
+    #[test]
+    fn pastebin_resolves_to_raw() {
+        assert_eq!(resolve_url("https://pastebin.com/abc123").unwrap().raw_url, "https://pastebin.com/raw/abc123");
+    }
+
+    #[test]
+    fn file_url_keeps_line_anchor() {
+        let resolved = resolve_url("file:///home/user/src/lib.rs#L10-L40").unwrap();
+        assert_eq!(resolved.raw_url, "file:///home/user/src/lib.rs");
+        assert_eq!(resolved.lines, Some((10, 40)));
+        assert_eq!(resolved.file_name.as_deref(), Some("lib.rs"));
+    }
+
+    #[test]
+    fn line_range_argument_is_parsed() {
+        assert_eq!(parse_line_range("10:40").unwrap(), (10, 40));
+        assert_eq!(parse_line_range("7").unwrap(), (7, 7));
+        assert!(parse_line_range("40:10").is_err());
+        assert!(parse_line_range("0:3").is_err());
+    }
+
+    #[test]
+    fn lines_are_extracted() {
+        let content = "one\ntwo\nthree\nfour\n";
+        assert_eq!(extract_lines(content, (2, 3)).unwrap(), "two\nthree\n");
+        assert_eq!(extract_lines(content, (4, 10)).unwrap(), "four\n");
+        assert!(extract_lines(content, (5, 6)).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+//! Selection of several snippets for the batch commands `rm` and `tag`.
+//!
+//! Snippets are selected by name patterns, by tags and by a search query; a snippet is selected
+//! if it matches all of the given criteria.
+
+use anyhow::{Result, bail};
+use regex::Regex;
+
+use crate::storage::{Snippet, SnippetStorage};
+
+/// A glob pattern matching snippet names, e.g. `tmp/*` or `rust/**/test-*`.
+///
+/// `*` and `?` do not match across `/`, so `tmp/*` does not select `tmp/old/a`; `**` does.
+#[derive(Debug, Clone)]
+pub struct NamePattern(Regex);
+
+impl NamePattern {
+    /// Compiles `pattern`, comparing case-insensitively if `ignore_case` is set.
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Self> {
+        let mut regex = String::from(if ignore_case { "(?i)^" } else { "^" });
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.next_if_eq(&'*').is_some() => regex.push_str(".*"),
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+            }
+        }
+        regex.push('$');
+        Ok(Self(Regex::new(&regex)?))
+    }
+
+    /// Whether `name` matches the pattern.
+    pub fn matches(&self, name: &str) -> bool {
+        self.0.is_match(name)
+    }
+}
+
+/// Criteria selecting snippets; empty criteria are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct Selector {
+    /// Names matching any of these patterns.
+    pub patterns: Vec<NamePattern>,
+    /// Snippets with all of these tags.
+    pub tags: Vec<String>,
+    /// Snippets whose name, description, tags or content contain every word of this query, regardless of case.
+    pub query: Option<String>,
+}
+
+impl Selector {
+    /// Whether the selector has no criteria, which would select every snippet.
+    ///
+    /// A blank query has no words to match, so it does not count as a criterion.
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty() && self.tags.is_empty() && self.query.as_deref().is_none_or(|q| q.trim().is_empty())
+    }
+
+    /// Names of the selected snippets in `storage`, in name order.
+    ///
+    /// Fails if the selector is empty, so that a batch never applies to the whole store by accident.
+    /// Snippets are only read, and decrypted, if tags or a query are given.
+    pub fn select(&self, storage: &dyn SnippetStorage) -> Result<Vec<String>> {
+        if self.is_empty() {
+            bail!("Select snippets by name pattern, tag or query");
+        }
+        let mut selected = Vec::new();
+        for name in storage.names()? {
+            if !self.patterns.is_empty() && !self.patterns.iter().any(|pattern| pattern.matches(&name)) {
+                continue;
+            }
+            if self.tags.is_empty() && self.query.is_none() {
+                selected.push(name);
+                continue;
+            }
+            if storage.get(&name)?.is_some_and(|snippet| self.matches_snippet(&name, &snippet)) {
+                selected.push(name);
+            }
+        }
+        Ok(selected)
+    }
+
+    /// Whether `snippet` has the tags and matches the query of the selector.
+    fn matches_snippet(&self, name: &str, snippet: &Snippet) -> bool {
+        if !self.tags.iter().all(|tag| snippet.tags.contains(tag)) {
+            return false;
+        }
+        let Some(query) = &self.query else {
+            return true;
+        };
+        let text = [name, snippet.description.as_deref().unwrap_or_default(), &snippet.tags.join(" "), &snippet.content]
+            .join("\n")
+            .to_lowercase();
+        query.split_whitespace().all(|word| text.contains(&word.to_lowercase()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::SqliteStorage;
+
+    #[test]
+    fn patterns_stay_within_namespaces_unless_doubled() {
+        let pattern = |pattern: &str| NamePattern::new(pattern, false).unwrap();
+        assert!(pattern("tmp/*").matches("tmp/a"));
+        assert!(!pattern("tmp/*").matches("tmp/old/a"));
+        assert!(pattern("tmp/**").matches("tmp/old/a"));
+        assert!(pattern("a?c").matches("abc"));
+        assert!(!pattern("a.c").matches("abc"));
+        assert!(!pattern("TMP/*").matches("tmp/a"));
+        assert!(NamePattern::new("TMP/*", true).unwrap().matches("tmp/a"));
+    }
+
+    #[test]
+    fn selector_combines_patterns_tags_and_query() {
+        let mut storage = SqliteStorage::open(":memory:").unwrap();
+        let mut save = |name: &str, tags: &[&str], content: &str| {
+            let mut snippet = Snippet::new(content.into());
+            snippet.tags = tags.iter().map(|tag| tag.to_string()).collect();
+            storage.save(&name.parse().unwrap(), &snippet).unwrap();
+        };
+        save("tmp/a", &["scratch"], "curl localhost");
+        save("tmp/b", &[], "docker ps");
+        save("ops/backup", &["scratch"], "rsync -a /srv Backup");
+
+        let select = |selector: Selector| selector.select(&storage).unwrap();
+        let patterns = vec![NamePattern::new("tmp/*", false).unwrap()];
+        assert_eq!(select(Selector { patterns: patterns.clone(), ..Selector::default() }), ["tmp/a", "tmp/b"]);
+        let tags = vec!["scratch".to_owned()];
+        assert_eq!(select(Selector { tags: tags.clone(), ..Selector::default() }), ["ops/backup", "tmp/a"]);
+        assert_eq!(select(Selector { patterns, tags, query: None }), ["tmp/a"]);
+        assert_eq!(select(Selector { query: Some("rsync backup".into()), ..Selector::default() }), ["ops/backup"]);
+        assert!(Selector::default().select(&storage).is_err());
+        for blank in ["", "  "] {
+            assert!(Selector { query: Some(blank.into()), ..Selector::default() }.select(&storage).is_err());
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! A read-only HTTP interface to the snippet store, started by the `serve` command.
+//!
+//! Routes:
+//!
+//! - `GET /snippets` lists the snippet names, one per line;
+//! - `GET /snippets/<namespace>/` lists the names in a namespace, collapsing nested namespaces;
+//! - `GET /snippets/<name>` returns the content of a snippet, with the name percent-encoded as needed;
+//! - `GET /metrics` returns storage metrics in the Prometheus text format, if `server.metrics` is enabled.
+//!
+//! The storage is opened per request from the active configuration, so a reloaded `storage`
+//! value takes effect on the next request. Encrypted content is only served if the passphrase
+//! is set in `SNIPPETS_APP_PASSPHRASE`; otherwise it is answered with 403.
+//!
+//! Reads are only counted in an SQLite store. Counting a read in a JSON store rewrites the whole
+//! file, which would race with the commands run alongside the server and could lose their changes.
+
+use anyhow::{Result, anyhow};
+use percent_encoding::percent_decode_str;
+use std::{borrow::Cow, sync::Arc, time::Instant};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{error, field, info, info_span};
+
+use crate::{
+    crypto::{CryptoError, PASSPHRASE_VAR, PassphraseSource},
+    logging::elapsed_ms,
+    name::browse,
+    metrics::{MeteredStorage, Metrics, store_stats},
+    reload::LiveConfig,
+    storage::{SnippetStorage, open_storage},
+};
+
+/// Serves snippets on `listen` until the process is stopped.
+pub fn serve(listen: &str, live: &LiveConfig) -> Result<()> {
+    let server = Server::http(listen).map_err(|err| anyhow!("Failed to listen on {listen}: {err}"))?;
+    info!("Listening on {}", listen);
+    let metrics = Arc::new(Metrics::default());
+    for request in server.incoming_requests() {
+        let span = info_span!(
+            "request",
+            method = %request.method(),
+            url = request.url(),
+            status = field::Empty,
+            bytes = field::Empty
+        );
+        let _entered = span.enter();
+        let started = Instant::now();
+        let (status, body) = match handle(&request, live, &metrics) {
+            Ok(response) => response,
+            Err(err) if err.downcast_ref::<CryptoError>() == Some(&CryptoError::Locked) => {
+                (403, "Snippet is encrypted\n".to_owned())
+            }
+            Err(err) => {
+                error!("{} {} failed: {:#}", request.method(), request.url(), err);
+                (500, format!("{err:#}\n"))
+            }
+        };
+        span.record("status", status);
+        span.record("bytes", body.len());
+        info!(duration_ms = elapsed_ms(started), "{} {} {}", request.method(), request.url(), status);
+        let content_type = Header::from_bytes("Content-Type", "text/plain; charset=utf-8").expect("valid header");
+        let response = Response::from_string(body).with_status_code(status).with_header(content_type);
+        if let Err(err) = request.respond(response) {
+            error!("Failed to send response: {}", err);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the status code and body for `request`.
+fn handle(request: &Request, live: &LiveConfig, metrics: &Arc<Metrics>) -> Result<(u16, String)> {
+    if request.method() != &Method::Get {
+        return Ok((405, "Method not allowed\n".to_owned()));
+    }
+    let config = live.get();
+    let metrics_enabled = config.config.server.metrics;
+    let storage = open_storage(&config.config.storage, PassphraseSource::from_env(PASSPHRASE_VAR))?;
+    let Some(path) = request_path(request.url()) else {
+        return Ok((400, "Invalid percent-encoding in path\n".to_owned()));
+    };
+    if path == "/metrics" && metrics_enabled {
+        return Ok((200, metrics.render(&store_stats(storage.as_ref())?)));
+    }
+    let mut storage: Box<dyn SnippetStorage> = if metrics_enabled {
+        Box::new(MeteredStorage::new(storage, Arc::clone(metrics)))
+    } else {
+        storage
+    };
+    match path.strip_prefix("/snippets") {
+        Some("" | "/") => Ok((200, storage.names()?.iter().map(|name| format!("{name}\n")).collect())),
+        Some(namespace) if namespace.ends_with('/') => {
+            let entries = browse(&storage.names()?, &namespace[1..], false, false);
+            Ok((200, entries.iter().map(|entry| format!("{entry}\n")).collect()))
+        }
+        Some(name) if name.starts_with('/') => match storage.get(&name[1..])? {
+            Some(snippet) => {
+                if config.config.usage.track_reads && config.config.storage_backend() == "SQLITE" {
+                    storage.record_read(&name[1..])?;
+                }
+                Ok((200, snippet.content))
+            }
+            None => Ok((404, "Snippet not found\n".to_owned())),
+        },
+        _ => Ok((404, "Not found\n".to_owned())),
+    }
+}
+
+/// Returns the percent-decoded path of a request URL, or `None` if it does not decode to UTF-8.
+fn request_path(url: &str) -> Option<Cow<'_, str>> {
+    let path = url.split('?').next().unwrap_or_default();
+    percent_decode_str(path).decode_utf8().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_percent_decoded() {
+        assert_eq!(request_path("/snippets/rust/let%20else?raw").as_deref(), Some("/snippets/rust/let else"));
+        assert_eq!(request_path("/snippets/caf%C3%A9").as_deref(), Some("/snippets/café"));
+        assert_eq!(request_path("/snippets/a%2Fb").as_deref(), Some("/snippets/a/b"));
+        assert_eq!(request_path("/snippets/%FF"), None);
+    }
+}
@@ -0,0 +1,439 @@
+//! Layered configuration of the snippets-app.
+//!
+//! Values are merged in the following priority (in ascending order):
+//!
+//! 1. defaults declared in [`DEFAULTS`];
+//! 2. the user config file `<XDG config dir>/snippets-app/config.toml`;
+//! 3. the project-local `.snippets.toml`, looked up from the current directory upwards;
+//! 4. environment variables with the `SNIPPETS_APP_` prefix (`SNIPPETS_APP_DOWNLOAD__MAX_SIZE` for nested keys);
+//! 5. command line flags.
+
+use anyhow::{Context, Result, bail};
+use config::{Config, Environment, File, FileFormat, FileSourceFile};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    env, fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    download::DownloadOptions,
+    secrets::{SecretScanner, SecretsAction},
+};
+
+/// Prefix of the environment variables overriding configuration values.
+pub const ENV_PREFIX: &str = "SNIPPETS_APP";
+
+/// Name of the project-local configuration file.
+pub const PROJECT_FILE: &str = ".snippets.toml";
+
+/// Every configuration key with its default value.
+pub const DEFAULTS: &[(&str, &str)] = &[
+    ("storage", "JSON:snippets.json"),
+    ("author", ""),
+    ("log_path", "snippets.log"),
+    ("log_level", "info"),
+    ("log_rotation", "never"),
+    ("log_max_files", "0"),
+    ("log_stderr", "false"),
+    ("log_format", "text"),
+    ("download.connect_timeout", "10"),
+    ("download.read_timeout", "30"),
+    ("download.max_size", "1048576"),
+    ("download.allow_binary", "false"),
+    ("server.listen", "127.0.0.1:8080"),
+    ("server.metrics", "false"),
+    ("secrets.action", "refuse"),
+    ("secrets.allowlist", ""),
+    ("names.ignore_case", "false"),
+    ("usage.track_reads", "true"),
+];
+
+/// Effective configuration of the snippets-app.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnippetsConfig {
+    /// Storage specification in the `<provider>:<path>` form, e.g. `SQLITE:snippets.sqlite`.
+    pub storage: String,
+    /// Author recorded on new snippets, empty to take it from git or the login name.
+    pub author: String,
+    /// File the log is written to.
+    pub log_path: PathBuf,
+    /// Log level or `tracing` filter directive.
+    pub log_level: String,
+    /// How often the log file is rotated; there is no size limit within a period.
+    pub log_rotation: LogRotation,
+    /// Number of rotated log files to keep, `0` to keep all of them.
+    pub log_max_files: usize,
+    /// Also write the log to stderr.
+    pub log_stderr: bool,
+    /// Format of the log file.
+    pub log_format: LogFormat,
+    /// Limits applied to `--download`, `--file` and `refresh`.
+    pub download: DownloadConfig,
+    /// Settings of the `serve` command.
+    pub server: ServerConfig,
+    /// Detection of secrets in saved content.
+    pub secrets: SecretsConfig,
+    /// Lookup of snippet names.
+    pub names: NamesConfig,
+    /// Tracking of snippet reads.
+    pub usage: UsageConfig,
+}
+
+impl SnippetsConfig {
+    /// Name of the storage provider, e.g. `SQLITE` for `SQLITE:snippets.sqlite`.
+    pub fn storage_backend(&self) -> &str {
+        self.storage.split_once(':').map_or(self.storage.as_str(), |(provider, _)| provider)
+    }
+
+    /// Path of the store, e.g. `snippets.sqlite` for `SQLITE:snippets.sqlite`.
+    pub fn storage_path(&self) -> &str {
+        self.storage.split_once(':').map_or("", |(_, path)| path)
+    }
+
+    /// Checks the values that deserialization alone cannot reject.
+    pub fn validate(&self) -> Result<()> {
+        if !["JSON:", "SQLITE:"].iter().any(|provider| self.storage.starts_with(provider)) {
+            bail!("storage: unknown storage provider '{}'", self.storage);
+        }
+        tracing_subscriber::EnvFilter::try_new(&self.log_level)
+            .with_context(|| format!("log_level: invalid filter '{}'", self.log_level))?;
+        self.secrets.scanner().context("secrets.allowlist")?;
+        for (key, value) in [
+            ("download.connect_timeout", self.download.connect_timeout),
+            ("download.read_timeout", self.download.read_timeout),
+            ("download.max_size", self.download.max_size),
+        ] {
+            if value == 0 {
+                bail!("{key}: must be greater than zero");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Rotation period of the log file.
+///
+/// Log files are rotated by time only: a file grows without limit until its period ends.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    /// Always append to `log_path`.
+    Never,
+    /// Start a new file every hour.
+    Hourly,
+    /// Start a new file every day.
+    Daily,
+    /// Start a new file every week.
+    Weekly,
+}
+
+impl fmt::Display for LogRotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Never => "never",
+            Self::Hourly => "hourly",
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Format of the log file.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line, including the fields of the enclosing spans.
+    Json,
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Text => "text",
+            Self::Json => "json",
+        })
+    }
+}
+
+/// The `[download]` section of the configuration.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DownloadConfig {
+    /// Connect timeout in seconds.
+    pub connect_timeout: u64,
+    /// Timeout for the whole request, including reading the body, in seconds.
+    pub read_timeout: u64,
+    /// Maximum size of downloaded content in bytes.
+    pub max_size: u64,
+    /// Accept content that does not look like text.
+    pub allow_binary: bool,
+}
+
+impl DownloadConfig {
+    /// Download options described by this section.
+    pub fn options(&self) -> DownloadOptions {
+        DownloadOptions {
+            connect_timeout: Duration::from_secs(self.connect_timeout),
+            read_timeout: Duration::from_secs(self.read_timeout),
+            max_size: self.max_size,
+            allow_binary: self.allow_binary,
+        }
+    }
+}
+
+/// The `[server]` section of the configuration.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerConfig {
+    /// Address the HTTP server listens on.
+    pub listen: String,
+    /// Record storage metrics and serve them at `/metrics`; other commands record no metrics.
+    pub metrics: bool,
+}
+
+/// The `[names]` section of the configuration.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NamesConfig {
+    /// Find snippets by name regardless of case when there is no exact match.
+    pub ignore_case: bool,
+}
+
+/// The `[usage]` section of the configuration.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UsageConfig {
+    /// Count reads and record the time of the last one; off for stores on read-only media.
+    ///
+    /// `serve` only counts reads in SQLite stores, where a read does not rewrite the whole store.
+    pub track_reads: bool,
+}
+
+/// The `[secrets]` section of the configuration.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SecretsConfig {
+    /// What to do with content that contains secrets.
+    pub action: SecretsAction,
+    /// Rule names or regular expressions of secrets to accept, as a list or a comma-separated string.
+    #[serde(deserialize_with = "list_or_comma_separated")]
+    pub allowlist: Vec<String>,
+}
+
+impl SecretsConfig {
+    /// Scanner applying this section.
+    pub fn scanner(&self) -> Result<SecretScanner> {
+        SecretScanner::new(self.action, &self.allowlist)
+    }
+}
+
+/// Accepts a list, or a comma-separated string as set by environment variables.
+fn list_or_comma_separated<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ListOrString {
+        List(Vec<String>),
+        String(String),
+    }
+
+    Ok(match ListOrString::deserialize(deserializer)? {
+        ListOrString::List(list) => list,
+        ListOrString::String(string) => string
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(str::to_owned)
+            .collect(),
+    })
+}
+
+/// The layer an effective configuration value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigOrigin {
+    /// Built-in default.
+    Default,
+    /// A configuration file.
+    File(PathBuf),
+    /// An environment variable.
+    Env(String),
+    /// A command line flag.
+    Cli,
+}
+
+impl fmt::Display for ConfigOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::File(path) => write!(f, "file {}", path.display()),
+            Self::Env(name) => write!(f, "env {name}"),
+            Self::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// A configuration value together with its origin, as printed by `config show`.
+#[derive(Debug, Clone)]
+pub struct ConfigEntry {
+    /// Dotted configuration key, e.g. `download.max_size`.
+    pub key: &'static str,
+    /// Effective value.
+    pub value: String,
+    /// Layer the value came from.
+    pub origin: ConfigOrigin,
+}
+
+/// Configuration merged from all layers.
+#[derive(Debug, Clone)]
+pub struct LoadedConfig {
+    /// Effective configuration.
+    pub config: SnippetsConfig,
+    /// Every configuration key with its effective value and origin.
+    pub entries: Vec<ConfigEntry>,
+    /// Configuration files that were found and merged, in ascending priority.
+    pub files: Vec<PathBuf>,
+}
+
+/// Path of the user configuration file, e.g. `~/.config/snippets-app/config.toml`.
+pub fn user_config_file() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("snippets-app").join("config.toml"))
+}
+
+/// Finds `.snippets.toml` in the current directory or its closest ancestor.
+pub fn project_config_file() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors().map(|dir| dir.join(PROJECT_FILE)).find(|path| path.is_file())
+}
+
+/// Loads the configuration, applying `cli_overrides` (dotted key and value) as the highest-priority layer.
+pub fn load_config(cli_overrides: &[(&str, String)]) -> Result<LoadedConfig> {
+    let files: Vec<PathBuf> = [user_config_file(), project_config_file()]
+        .into_iter()
+        .flatten()
+        .filter(|path| path.is_file())
+        .collect();
+
+    let mut file_layers = Vec::new();
+    for path in &files {
+        file_layers.push((path, Config::builder().add_source(file_source(path)).build()?));
+    }
+
+    let mut builder = Config::builder();
+    for (key, value) in DEFAULTS {
+        builder = builder.set_default(*key, *value)?;
+    }
+    for path in &files {
+        builder = builder.add_source(file_source(path));
+    }
+    builder = builder.add_source(env_source());
+    for (key, value) in cli_overrides {
+        builder = builder.set_override(*key, value.as_str())?;
+    }
+    let merged = builder.build().context("Failed to load configuration")?;
+    let config: SnippetsConfig = merged
+        .clone()
+        .try_deserialize()
+        .context("Invalid configuration")?;
+    config.validate().context("Invalid configuration")?;
+
+    let entries = DEFAULTS
+        .iter()
+        .map(|(key, _)| {
+            let origin = if cli_overrides.iter().any(|(cli_key, _)| cli_key == key) {
+                ConfigOrigin::Cli
+            } else if env::var_os(env_var_name(key)).is_some() {
+                ConfigOrigin::Env(env_var_name(key))
+            } else {
+                file_layers
+                    .iter()
+                    .rev()
+                    .find(|(_, layer)| layer.get::<config::Value>(key).is_ok())
+                    .map_or(ConfigOrigin::Default, |(path, _)| ConfigOrigin::File((*path).clone()))
+            };
+            let value = merged
+                .get::<config::Value>(key)
+                .map(|value| value.to_string())
+                .unwrap_or_default();
+            ConfigEntry { key, value, origin }
+        })
+        .collect();
+
+    Ok(LoadedConfig { config, entries, files })
+}
+
+/// Paths of every configuration file that is merged when it exists, in ascending priority.
+///
+/// Unlike [`LoadedConfig::files`], this includes files that have not been created yet,
+/// so that a long-running process can pick them up once they appear.
+pub fn config_file_candidates() -> Vec<PathBuf> {
+    let project = project_config_file().or_else(|| env::current_dir().ok().map(|dir| dir.join(PROJECT_FILE)));
+    [user_config_file(), project].into_iter().flatten().collect()
+}
+
+/// Name of the environment variable overriding `key`, e.g. `SNIPPETS_APP_DOWNLOAD__MAX_SIZE`.
+pub fn env_var_name(key: &str) -> String {
+    format!("{ENV_PREFIX}_{}", key.replace('.', "__").to_uppercase())
+}
+
+fn env_source() -> Environment {
+    Environment::with_prefix(ENV_PREFIX).prefix_separator("_").separator("__")
+}
+
+fn file_source(path: &Path) -> File<FileSourceFile, FileFormat> {
+    File::from(path).format(FileFormat::Toml)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SnippetsConfig {
+        SnippetsConfig {
+            storage: "JSON:snippets.json".into(),
+            author: String::new(),
+            log_path: "snippets.log".into(),
+            log_level: "info".into(),
+            log_rotation: LogRotation::Never,
+            log_max_files: 0,
+            log_stderr: false,
+            log_format: LogFormat::Text,
+            download: DownloadConfig {
+                connect_timeout: 10,
+                read_timeout: 30,
+                max_size: 1024,
+                allow_binary: false,
+            },
+            server: ServerConfig {
+                listen: "127.0.0.1:8080".into(),
+                metrics: false,
+            },
+            secrets: SecretsConfig {
+                action: SecretsAction::Refuse,
+                allowlist: Vec::new(),
+            },
+            names: NamesConfig { ignore_case: false },
+            usage: UsageConfig { track_reads: true },
+        }
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(config().validate().is_ok());
+        assert!(SnippetsConfig { storage: "XML:a.xml".into(), ..config() }.validate().is_err());
+        assert!(SnippetsConfig { log_level: "info,[".into(), ..config() }.validate().is_err());
+        let mut zero_timeout = config();
+        zero_timeout.download.read_timeout = 0;
+        assert!(zero_timeout.validate().is_err());
+        let mut bad_pattern = config();
+        bad_pattern.secrets.allowlist = vec!["(".into()];
+        assert!(bad_pattern.validate().is_err());
+    }
+
+    #[test]
+    fn env_var_names_use_double_underscore_for_sections() {
+        assert_eq!(env_var_name("download.max_size"), "SNIPPETS_APP_DOWNLOAD__MAX_SIZE");
+        assert_eq!(env_var_name("log_level"), "SNIPPETS_APP_LOG_LEVEL");
+    }
+}
@@ -0,0 +1,1341 @@
+//! Snippet storage backends selected by `SNIPPETS_APP_STORAGE`.
+//!
+//! Both backends can encrypt snippet content at rest, see [`crate::crypto`]. The JSON backend
+//! encrypts the whole snippet map, or only the content of private snippets; the SQLite backend
+//! encrypts the `content` column of the affected rows, keeping names and metadata queryable.
+
+use anyhow::{Context, Result, bail};
+use rusqlite::{
+    Connection, OpenFlags, OptionalExtension, Row, ToSql, params,
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, Type, ValueRef},
+};
+use serde::{Deserialize, Deserializer, Serialize, de::DeserializeOwned};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    audit::{AuditEntry, AuditOperation},
+    crypto::{CryptoError, EncryptionHeader, EncryptionScope, Keyring, PassphraseSource},
+    name::SnippetName,
+    usage::Usage,
+};
+
+/// Where a downloaded snippet came from, used to refresh it later.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    /// URL the snippet was downloaded from.
+    pub url: String,
+    /// `ETag` header of the last successful download.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    /// `Last-Modified` header of the last successful download, or the modification time of a local file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+/// How the content of a snippet was entered.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    /// Piped into stdin.
+    Stdin,
+    /// Downloaded from an HTTP(S) URL.
+    Url,
+    /// Read from a local file.
+    File,
+    /// Written in `$VISUAL` or `$EDITOR`.
+    Editor,
+}
+
+impl fmt::Display for SourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Stdin => "stdin",
+            Self::Url => "url",
+            Self::File => "file",
+            Self::Editor => "editor",
+        })
+    }
+}
+
+impl FromStr for SourceKind {
+    type Err = anyhow::Error;
+
+    fn from_str(kind: &str) -> Result<Self> {
+        match kind {
+            "stdin" => Ok(Self::Stdin),
+            "url" => Ok(Self::Url),
+            "file" => Ok(Self::File),
+            "editor" => Ok(Self::Editor),
+            _ => bail!("Unknown source kind '{kind}'"),
+        }
+    }
+}
+
+/// A stored snippet.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    /// Snippet content.
+    pub content: String,
+    /// Creation time in RFC 3339 format.
+    pub created_at: String,
+    /// Time of the last content change in RFC 3339 format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+    /// Content revision, starting at 1 and bumped on every change.
+    #[serde(default = "first_revision")]
+    pub revision: u32,
+    /// One-line description.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Language of the content, e.g. `rust`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Author, e.g. `Jane Doe <jane@example.com>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// How the content was entered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_kind: Option<SourceKind>,
+    /// Download source, if the snippet was downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
+    /// Number of times the snippet was read.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub read_count: u64,
+    /// Time of the last read in RFC 3339 format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_accessed_at: Option<String>,
+    /// Time the snippet was moved to the trash in RFC 3339 format; trashed snippets are hidden.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<String>,
+    /// Free-form tags, e.g. `cli` or `async`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Whether the content is encrypted even if the store only encrypts private snippets.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub private: bool,
+    /// Free-form key/value attributes, e.g. `license = MIT`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, String>,
+}
+
+fn is_zero(count: &u64) -> bool {
+    *count == 0
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn first_revision() -> u32 {
+    1
+}
+
+impl Snippet {
+    /// Creates a new snippet with the current time as creation time.
+    pub fn new(content: String) -> Self {
+        Self {
+            content,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            updated_at: None,
+            revision: 1,
+            description: None,
+            language: None,
+            author: None,
+            source_kind: None,
+            source: None,
+            read_count: 0,
+            last_accessed_at: None,
+            deleted_at: None,
+            tags: Vec::new(),
+            private: false,
+            attributes: BTreeMap::new(),
+        }
+    }
+
+    /// Replaces the content, recording a new revision.
+    pub fn update_content(&mut self, content: String) {
+        self.content = content;
+        self.updated_at = Some(chrono::Utc::now().to_rfc3339());
+        self.revision += 1;
+    }
+}
+
+/// A snippet in the trash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashedSnippet {
+    /// Name of the snippet.
+    pub name: String,
+    /// Time the snippet was moved to the trash in RFC 3339 format.
+    pub deleted_at: String,
+}
+
+/// On-disk representation of a snippet, accepting the legacy `(content, created_at)` tuple.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredSnippet {
+    Legacy(String, String),
+    Full(Box<Snippet>),
+}
+
+impl From<StoredSnippet> for Snippet {
+    fn from(stored: StoredSnippet) -> Self {
+        match stored {
+            StoredSnippet::Legacy(content, created_at) => Self {
+                created_at,
+                ..Self::new(content)
+            },
+            StoredSnippet::Full(snippet) => *snippet,
+        }
+    }
+}
+
+fn deserialize_snippets<'de, D>(deserializer: D) -> Result<BTreeMap<String, Snippet>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let stored = BTreeMap::<String, StoredSnippet>::deserialize(deserializer)?;
+    Ok(stored.into_iter().map(|(name, snippet)| (name, snippet.into())).collect())
+}
+
+/// Common interface of the snippet storage backends.
+pub trait SnippetStorage {
+    /// Returns the snippet stored under `name`.
+    fn get(&self, name: &str) -> Result<Option<Snippet>>;
+    /// Creates or replaces the snippet stored under `name`.
+    fn save(&mut self, name: &SnippetName, snippet: &Snippet) -> Result<()>;
+    /// Moves the snippet stored under `name` to the trash, returning whether it existed.
+    ///
+    /// Trashed snippets are hidden from every other method until restored. Saving or renaming
+    /// a snippet to the name of a trashed one replaces the trashed one.
+    fn remove(&mut self, name: &str) -> Result<bool>;
+    /// Moves the snippet stored under `from` to `to`, returning whether it existed.
+    ///
+    /// Fails if a snippet is already stored under `to`. The source name is not validated,
+    /// so snippets saved before names were validated can be renamed to valid names.
+    fn rename(&mut self, from: &str, to: &SnippetName) -> Result<bool>;
+    /// Returns the snippets in the trash in name order.
+    fn trash(&self) -> Result<Vec<TrashedSnippet>>;
+    /// Moves the snippet `name` out of the trash, returning whether it was in the trash.
+    fn restore(&mut self, name: &str) -> Result<bool>;
+    /// Deletes the snippet `name` in the trash for good, returning whether it was in the trash.
+    ///
+    /// Not audited: the deletion was recorded when the snippet was moved to the trash.
+    fn purge(&mut self, name: &str) -> Result<bool>;
+    /// Returns the names of all stored snippets in sorted order.
+    fn names(&self) -> Result<Vec<String>>;
+    /// Returns every recorded mutation in chronological order.
+    fn audit_log(&self) -> Result<Vec<AuditEntry>>;
+    /// Counts a read of the snippet stored under `name` and records its time, if it exists.
+    fn record_read(&mut self, name: &str) -> Result<()>;
+    /// Replaces the source of the snippet stored under `name` with `source`, if it exists.
+    ///
+    /// Not audited: used to keep the cache validators of a source current when its content is unchanged.
+    fn record_source(&mut self, name: &str, source: &Source) -> Result<()>;
+    /// Returns the usage of every stored snippet in name order, without decrypting any content.
+    fn usage(&self) -> Result<Vec<Usage>>;
+    /// Writes the store if it does not exist yet, and encrypts it with the passphrase if one is given.
+    fn init(&mut self, encryption: Option<(&str, EncryptionScope)>) -> Result<()>;
+    /// Re-encrypts the content of an encrypted store with a key derived from a new passphrase.
+    fn rekey(&mut self, passphrase: &str) -> Result<()>;
+    /// Derives the key of an encrypted store now instead of on the first read of encrypted content.
+    ///
+    /// Called before taking over the terminal, where a passphrase prompt could not be answered.
+    fn unlock(&self) -> Result<()>;
+    /// Runs `operations` as one batch: either all of their changes are stored or, if they fail, none.
+    ///
+    /// A batch started inside another batch becomes part of it.
+    fn batch(&mut self, operations: &mut dyn FnMut(&mut dyn SnippetStorage) -> Result<()>) -> Result<()>;
+}
+
+/// Opens the storage described by a `<provider>:<path>` specification, e.g. `JSON:snippets.json`.
+///
+/// The passphrase is only requested once encrypted content is accessed.
+pub fn open_storage(spec: &str, passphrase: PassphraseSource) -> Result<Box<dyn SnippetStorage>> {
+    if let Some(path) = spec.strip_prefix("JSON:") {
+        Ok(Box::new(JsonStorage::open_with_passphrase(path, passphrase)?))
+    } else if let Some(path) = spec.strip_prefix("SQLITE:") {
+        Ok(Box::new(SqliteStorage::open_with_passphrase(path, passphrase)?))
+    } else {
+        bail!("Unknown storage provider: {spec}")
+    }
+}
+
+/// Opens the existing storage described by `spec` for reading only, e.g. to complete names on every <TAB>.
+///
+/// Unlike [`open_storage`], an SQLite database is neither created nor migrated, and every write fails.
+pub fn open_storage_read_only(spec: &str, passphrase: PassphraseSource) -> Result<Box<dyn SnippetStorage>> {
+    if let Some(path) = spec.strip_prefix("JSON:") {
+        Ok(Box::new(JsonStorage::open_with_passphrase(path, passphrase)?))
+    } else if let Some(path) = spec.strip_prefix("SQLITE:") {
+        Ok(Box::new(SqliteStorage::open_read_only(path, passphrase)?))
+    } else {
+        bail!("Unknown storage provider: {spec}")
+    }
+}
+
+/// Refuses to save a private snippet into a store without encryption.
+fn check_private(keyring: Option<&Keyring>, snippet: &Snippet) -> Result<()> {
+    if snippet.private && keyring.is_none() {
+        bail!("Private snippets need an encrypted store: run `init --encrypt private` first");
+    }
+    Ok(())
+}
+
+/// Encrypts `content` if `encrypted` is set.
+fn seal_content(keyring: Option<&Keyring>, encrypted: bool, content: &str) -> Result<String> {
+    match keyring {
+        Some(keyring) if encrypted => Ok(keyring.cipher()?.encrypt_str(content)),
+        _ => Ok(content.to_owned()),
+    }
+}
+
+/// Decrypts `content` if `encrypted` is set.
+fn open_content(keyring: Option<&Keyring>, encrypted: bool, content: String) -> Result<String> {
+    match keyring {
+        Some(keyring) if encrypted => keyring.cipher()?.decrypt_str(&content),
+        _ => Ok(content),
+    }
+}
+
+/// Represents the snippet store for JSON storage.
+#[derive(Serialize, Deserialize, Default)]
+pub struct SnippetStore {
+    /// Key derivation parameters, if the store is encrypted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionHeader>,
+    /// The encrypted snippet map, if all content is encrypted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    /// Snippets by name.
+    #[serde(default, deserialize_with = "deserialize_snippets")]
+    pub snippets: BTreeMap<String, Snippet>,
+}
+
+/// Snippet storage backed by a single JSON file.
+///
+/// Mutations are audited in a sidecar JSON Lines file next to it, e.g. `snippets.audit.jsonl`.
+///
+/// A store encrypted with [`EncryptionScope::All`] is decrypted when it is opened. With
+/// [`EncryptionScope::Private`], private snippets are kept encrypted in memory and only
+/// decrypted when they are read.
+pub struct JsonStorage {
+    path: PathBuf,
+    audit_path: PathBuf,
+    store: SnippetStore,
+    keyring: Option<Keyring>,
+    /// Audit entries of the running batch, written with the store when it succeeds.
+    pending_audit: Option<Vec<AuditEntry>>,
+}
+
+impl JsonStorage {
+    /// Loads the store from `path`, starting empty if the file does not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_passphrase(path, PassphraseSource::None)
+    }
+
+    /// Loads the store from `path`, taking the passphrase of an encrypted store from `passphrase`.
+    pub fn open_with_passphrase(path: impl AsRef<Path>, passphrase: PassphraseSource) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut store: SnippetStore = match fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data)
+                .with_context(|| format!("Failed to parse JSON file {}", path.display()))?,
+            Err(_) => SnippetStore::default(),
+        };
+        let keyring = store.encryption.clone().map(|header| Keyring::new(header, passphrase));
+        if let Some(data) = store.data.take() {
+            let keyring = keyring
+                .as_ref()
+                .ok_or_else(|| CryptoError::Corrupted("encrypted data without an encryption header".into()))?;
+            let snippets = keyring.cipher()?.decrypt_str(&data)?;
+            store.snippets = serde_json::from_str(&snippets)
+                .map_err(|err| CryptoError::Corrupted(format!("invalid snippets: {err}")))?;
+        }
+        let audit_path = path.with_extension("audit.jsonl");
+        Ok(Self {
+            path,
+            audit_path,
+            store,
+            keyring,
+            pending_audit: None,
+        })
+    }
+
+    /// Writes the store, unless a batch is running and will write it at its end.
+    fn flush(&self) -> Result<()> {
+        if self.pending_audit.is_some() {
+            return Ok(());
+        }
+        let data = match &self.keyring {
+            Some(keyring) if keyring.scope() == EncryptionScope::All => {
+                let snippets = serde_json::to_string(&self.store.snippets)?;
+                serde_json::to_string_pretty(&SnippetStore {
+                    encryption: Some(keyring.header().clone()),
+                    data: Some(keyring.cipher()?.encrypt_str(&snippets)),
+                    snippets: BTreeMap::new(),
+                })?
+            }
+            _ => serde_json::to_string_pretty(&self.store)?,
+        };
+        fs::write(&self.path, data).context("Failed to write JSON file")
+    }
+
+    /// The snippet stored under `name`, unless it is in the trash.
+    fn live(&self, name: &str) -> Option<&Snippet> {
+        self.store.snippets.get(name).filter(|snippet| snippet.deleted_at.is_none())
+    }
+
+    /// The snippets that are not in the trash, in name order.
+    fn live_snippets(&self) -> impl Iterator<Item = (&String, &Snippet)> {
+        self.store.snippets.iter().filter(|(_, snippet)| snippet.deleted_at.is_none())
+    }
+
+    /// Whether the in-memory content of `snippet` is encrypted.
+    fn is_sealed(&self, snippet: &Snippet) -> bool {
+        snippet.private && self.keyring.as_ref().is_some_and(|keyring| keyring.scope() == EncryptionScope::Private)
+    }
+
+    /// Returns `snippet` as kept in memory, with the content encrypted if needed.
+    fn seal(&self, snippet: &Snippet) -> Result<Snippet> {
+        check_private(self.keyring.as_ref(), snippet)?;
+        Ok(Snippet {
+            content: seal_content(self.keyring.as_ref(), self.is_sealed(snippet), &snippet.content)?,
+            ..snippet.clone()
+        })
+    }
+
+    /// Returns a snippet kept in memory with the content decrypted.
+    fn unseal(&self, snippet: &Snippet) -> Result<Snippet> {
+        Ok(Snippet {
+            content: open_content(self.keyring.as_ref(), self.is_sealed(snippet), snippet.content.clone())?,
+            ..snippet.clone()
+        })
+    }
+
+    /// Re-encrypts every snippet with `keyring` and writes the store.
+    fn reencrypt(&mut self, keyring: Keyring) -> Result<()> {
+        let snippets = self
+            .store
+            .snippets
+            .iter()
+            .map(|(name, snippet)| Ok((name.clone(), self.unseal(snippet)?)))
+            .collect::<Result<Vec<_>>>()?;
+        self.store.encryption = Some(keyring.header().clone());
+        self.keyring = Some(keyring);
+        self.store.snippets = snippets
+            .into_iter()
+            .map(|(name, snippet)| Ok((name, self.seal(&snippet)?)))
+            .collect::<Result<_>>()?;
+        self.flush()
+    }
+
+    fn append_audit(&mut self, entry: &AuditEntry) -> Result<()> {
+        if let Some(pending) = &mut self.pending_audit {
+            pending.push(entry.clone());
+            return Ok(());
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.audit_path)
+            .with_context(|| format!("Failed to open audit log {}", self.audit_path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(entry)?).context("Failed to write audit log")
+    }
+}
+
+impl SnippetStorage for JsonStorage {
+    fn get(&self, name: &str) -> Result<Option<Snippet>> {
+        self.live(name).map(|snippet| self.unseal(snippet)).transpose()
+    }
+
+    fn save(&mut self, name: &SnippetName, snippet: &Snippet) -> Result<()> {
+        let sealed = self.seal(snippet)?;
+        let before = self.get(name)?;
+        self.store.snippets.insert(name.to_string(), sealed);
+        self.flush()?;
+        self.append_audit(&AuditEntry::new(
+            name,
+            before.as_ref().map(|before| before.content.as_str()),
+            Some(&snippet.content),
+        ))
+    }
+
+    fn remove(&mut self, name: &str) -> Result<bool> {
+        let Some(before) = self.get(name)? else {
+            return Ok(false);
+        };
+        let snippet = self.store.snippets.get_mut(name).expect("snippet was found");
+        snippet.deleted_at = Some(chrono::Utc::now().to_rfc3339());
+        self.flush()?;
+        self.append_audit(&AuditEntry::new(name, Some(&before.content), None))?;
+        Ok(true)
+    }
+
+    fn rename(&mut self, from: &str, to: &SnippetName) -> Result<bool> {
+        let Some(snippet) = self.get(from)? else {
+            return Ok(false);
+        };
+        if self.live(to).is_some() {
+            bail!("Snippet '{to}' already exists");
+        }
+        let sealed = self.store.snippets.remove(from).expect("snippet was found");
+        self.store.snippets.insert(to.to_string(), sealed);
+        self.flush()?;
+        self.append_audit(&AuditEntry::new(from, Some(&snippet.content), None))?;
+        self.append_audit(&AuditEntry::new(to, None, Some(&snippet.content)))?;
+        Ok(true)
+    }
+
+    fn names(&self) -> Result<Vec<String>> {
+        Ok(self.live_snippets().map(|(name, _)| name.clone()).collect())
+    }
+
+    fn trash(&self) -> Result<Vec<TrashedSnippet>> {
+        Ok(self
+            .store
+            .snippets
+            .iter()
+            .filter_map(|(name, snippet)| {
+                Some(TrashedSnippet {
+                    name: name.clone(),
+                    deleted_at: snippet.deleted_at.clone()?,
+                })
+            })
+            .collect())
+    }
+
+    fn restore(&mut self, name: &str) -> Result<bool> {
+        let Some(snippet) = self.store.snippets.get_mut(name).filter(|snippet| snippet.deleted_at.is_some()) else {
+            return Ok(false);
+        };
+        snippet.deleted_at = None;
+        let restored = self.get(name)?.expect("snippet was restored");
+        self.flush()?;
+        self.append_audit(&AuditEntry::new(name, None, Some(&restored.content)))?;
+        Ok(true)
+    }
+
+    fn purge(&mut self, name: &str) -> Result<bool> {
+        if self.store.snippets.get(name).is_none_or(|snippet| snippet.deleted_at.is_none()) {
+            return Ok(false);
+        }
+        self.store.snippets.remove(name);
+        self.flush()?;
+        Ok(true)
+    }
+
+    fn record_read(&mut self, name: &str) -> Result<()> {
+        let Some(snippet) = self.store.snippets.get_mut(name).filter(|snippet| snippet.deleted_at.is_none()) else {
+            return Ok(());
+        };
+        snippet.read_count += 1;
+        snippet.last_accessed_at = Some(chrono::Utc::now().to_rfc3339());
+        self.flush()
+    }
+
+    fn record_source(&mut self, name: &str, source: &Source) -> Result<()> {
+        let Some(snippet) = self.store.snippets.get_mut(name).filter(|snippet| snippet.deleted_at.is_none()) else {
+            return Ok(());
+        };
+        snippet.source = Some(source.clone());
+        self.flush()
+    }
+
+    fn usage(&self) -> Result<Vec<Usage>> {
+        Ok(self
+            .live_snippets()
+            .map(|(name, snippet)| Usage {
+                name: name.clone(),
+                read_count: snippet.read_count,
+                last_accessed_at: snippet.last_accessed_at.clone(),
+                created_at: snippet.created_at.clone(),
+                updated_at: snippet.updated_at.clone(),
+            })
+            .collect())
+    }
+
+    fn init(&mut self, encryption: Option<(&str, EncryptionScope)>) -> Result<()> {
+        match encryption {
+            Some(_) if self.keyring.is_some() => {
+                bail!("The store is already encrypted: use `rekey` to change the passphrase")
+            }
+            Some((passphrase, scope)) => self.reencrypt(Keyring::create(passphrase, scope)?),
+            None => self.flush(),
+        }
+    }
+
+    fn rekey(&mut self, passphrase: &str) -> Result<()> {
+        let Some(keyring) = &self.keyring else {
+            bail!("The store is not encrypted: use `init --encrypt` to encrypt it");
+        };
+        keyring.cipher()?;
+        let keyring = Keyring::create(passphrase, keyring.scope())?;
+        self.reencrypt(keyring)
+    }
+
+    fn unlock(&self) -> Result<()> {
+        self.keyring.as_ref().map(Keyring::cipher).transpose()?;
+        Ok(())
+    }
+
+    fn batch(&mut self, operations: &mut dyn FnMut(&mut dyn SnippetStorage) -> Result<()>) -> Result<()> {
+        if self.pending_audit.is_some() {
+            return operations(self);
+        }
+        let snippets = self.store.snippets.clone();
+        self.pending_audit = Some(Vec::new());
+        let outcome = operations(self);
+        let entries = self.pending_audit.take().unwrap_or_default();
+        if outcome.is_err() {
+            self.store.snippets = snippets;
+            return outcome;
+        }
+        self.flush()?;
+        entries.iter().try_for_each(|entry| self.append_audit(entry))
+    }
+
+    fn audit_log(&self) -> Result<Vec<AuditEntry>> {
+        let data = match fs::read_to_string(&self.audit_path) {
+            Ok(data) => data,
+            Err(_) => return Ok(Vec::new()),
+        };
+        data.lines()
+            .enumerate()
+            .map(|(index, line)| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("Failed to parse {} line {}", self.audit_path.display(), index + 1))
+            })
+            .collect()
+    }
+}
+
+/// Snippet storage backed by an SQLite database.
+///
+/// The encryption header of an encrypted database is kept in the single row of the `encryption` table.
+pub struct SqliteStorage {
+    conn: Connection,
+    keyring: Option<Keyring>,
+}
+
+impl SqliteStorage {
+    /// Opens the database at `path`, creating and migrating the schema as needed.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_passphrase(path, PassphraseSource::None)
+    }
+
+    /// Opens the database at `path`, taking the passphrase of an encrypted database from `passphrase`.
+    pub fn open_with_passphrase(path: impl AsRef<Path>, passphrase: PassphraseSource) -> Result<Self> {
+        let conn = Connection::open(path).context("Failed to open SQLite DB")?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS snippets (
+                name TEXT PRIMARY KEY,
+                content TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )
+        .context("Failed to create table")?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp TEXT NOT NULL,
+                user TEXT NOT NULL,
+                host TEXT NOT NULL,
+                operation TEXT NOT NULL,
+                name TEXT NOT NULL,
+                hash_before TEXT,
+                hash_after TEXT
+            );
+            CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+            BEGIN SELECT RAISE(ABORT, 'audit log is append-only'); END;
+            CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+            BEGIN SELECT RAISE(ABORT, 'audit log is append-only'); END;",
+        )
+        .context("Failed to create audit log")?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS encryption (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                header TEXT NOT NULL
+            )",
+            [],
+        )
+        .context("Failed to create encryption table")?;
+        for (column, definition) in [
+            ("updated_at", "TEXT"),
+            ("revision", "INTEGER NOT NULL DEFAULT 1"),
+            ("description", "TEXT"),
+            ("language", "TEXT"),
+            ("source_url", "TEXT"),
+            ("etag", "TEXT"),
+            ("last_modified", "TEXT"),
+            ("read_count", "INTEGER NOT NULL DEFAULT 0"),
+            ("private", "INTEGER NOT NULL DEFAULT 0"),
+            ("tags", "TEXT NOT NULL DEFAULT '[]'"),
+            ("author", "TEXT"),
+            ("source_kind", "TEXT"),
+            ("attributes", "TEXT NOT NULL DEFAULT '{}'"),
+            ("last_accessed_at", "TEXT"),
+            ("deleted_at", "TEXT"),
+        ] {
+            add_column_if_missing(&conn, column, definition)?;
+        }
+        Self::with_connection(conn, passphrase)
+    }
+
+    /// Opens the existing database at `path` without creating or migrating the schema.
+    pub fn open_read_only(path: impl AsRef<Path>, passphrase: PassphraseSource) -> Result<Self> {
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        let conn = Connection::open_with_flags(path, flags).context("Failed to open SQLite DB")?;
+        Self::with_connection(conn, passphrase)
+    }
+
+    /// Reads the encryption header of an opened database.
+    fn with_connection(conn: Connection, passphrase: PassphraseSource) -> Result<Self> {
+        let header: Option<String> = conn
+            .query_row("SELECT header FROM encryption", [], |row| row.get(0))
+            .optional()
+            .context("Failed to query encryption header")?;
+        let keyring = header
+            .map(|header| {
+                serde_json::from_str(&header)
+                    .map_err(|err| CryptoError::Corrupted(format!("invalid encryption header: {err}")))
+            })
+            .transpose()?
+            .map(|header| Keyring::new(header, passphrase));
+        Ok(Self { conn, keyring })
+    }
+
+    /// Re-encrypts every row with `keyring`, then vacuums the database to drop the old content.
+    fn reencrypt(&mut self, keyring: Keyring) -> Result<()> {
+        let tx = self.conn.savepoint()?;
+        let rows: Vec<(String, String, bool)> = tx
+            .prepare("SELECT name, content, private FROM snippets")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        for (name, content, private) in rows {
+            let content = open_content(self.keyring.as_ref(), is_encrypted(self.keyring.as_ref(), private), content)?;
+            let content = seal_content(Some(&keyring), is_encrypted(Some(&keyring), private), &content)?;
+            tx.execute("UPDATE snippets SET content = ?2 WHERE name = ?1", params![name, content])?;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO encryption (id, header) VALUES (1, ?1)",
+            [serde_json::to_string(keyring.header())?],
+        )?;
+        tx.commit().context("Failed to commit encryption")?;
+        self.keyring = Some(keyring);
+        self.conn.execute("VACUUM", []).context("Failed to vacuum database")?;
+        Ok(())
+    }
+}
+
+/// Whether the `content` column of a row is encrypted.
+fn is_encrypted(keyring: Option<&Keyring>, private: bool) -> bool {
+    keyring.is_some_and(|keyring| private || keyring.scope() == EncryptionScope::All)
+}
+
+impl ToSql for AuditOperation {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for AuditOperation {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|err: anyhow::Error| FromSqlError::Other(err.into()))
+    }
+}
+
+impl ToSql for SourceKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for SourceKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|err: anyhow::Error| FromSqlError::Other(err.into()))
+    }
+}
+
+/// Reads a column holding JSON text, such as `tags` or `attributes`.
+fn json_column<T: DeserializeOwned>(row: &Row<'_>, index: usize) -> rusqlite::Result<T> {
+    serde_json::from_str(&row.get::<_, String>(index)?)
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(err)))
+}
+
+fn current_content(conn: &Connection, keyring: Option<&Keyring>, name: &str) -> Result<Option<String>> {
+    stored_content(conn, keyring, name, false)
+}
+
+/// Content of the snippet `name`, of the one in the trash if `trashed` is set.
+fn stored_content(conn: &Connection, keyring: Option<&Keyring>, name: &str, trashed: bool) -> Result<Option<String>> {
+    let row: Option<(String, bool)> = conn
+        .query_row(
+            "SELECT content, private FROM snippets WHERE name = ?1 AND (deleted_at IS NOT NULL) = ?2",
+            params![name, trashed],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .context("Failed to query snippet")?;
+    row.map(|(content, private)| open_content(keyring, is_encrypted(keyring, private), content))
+        .transpose()
+}
+
+fn insert_audit(conn: &Connection, entry: &AuditEntry) -> Result<()> {
+    conn.execute(
+        "INSERT INTO audit_log (timestamp, user, host, operation, name, hash_before, hash_after)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            entry.timestamp,
+            entry.user,
+            entry.host,
+            entry.operation,
+            entry.name,
+            entry.hash_before,
+            entry.hash_after,
+        ],
+    )
+    .context("Failed to write audit log")?;
+    Ok(())
+}
+
+fn add_column_if_missing(conn: &Connection, column: &str, definition: &str) -> Result<()> {
+    let exists: bool = conn
+        .query_row("SELECT COUNT(*) > 0 FROM pragma_table_info('snippets') WHERE name = ?1", [column], |row| {
+            row.get(0)
+        })
+        .context("Failed to inspect table")?;
+    if !exists {
+        conn.execute(&format!("ALTER TABLE snippets ADD COLUMN {column} {definition}"), [])
+            .with_context(|| format!("Failed to add column '{column}'"))?;
+    }
+    Ok(())
+}
+
+impl SnippetStorage for SqliteStorage {
+    fn get(&self, name: &str) -> Result<Option<Snippet>> {
+        let snippet = self
+            .conn
+            .query_row(
+                "SELECT content, created_at, updated_at, revision, description, language,
+                        source_url, etag, last_modified, read_count, private, tags,
+                        author, source_kind, attributes, last_accessed_at
+                 FROM snippets WHERE name = ?1 AND deleted_at IS NULL",
+                [name],
+                |row| {
+                    let source = match row.get::<_, Option<String>>(6)? {
+                        Some(url) => Some(Source {
+                            url,
+                            etag: row.get(7)?,
+                            last_modified: row.get(8)?,
+                        }),
+                        None => None,
+                    };
+                    Ok(Snippet {
+                        content: row.get(0)?,
+                        created_at: row.get(1)?,
+                        updated_at: row.get(2)?,
+                        revision: row.get(3)?,
+                        description: row.get(4)?,
+                        language: row.get(5)?,
+                        source,
+                        read_count: row.get(9)?,
+                        last_accessed_at: row.get(15)?,
+                        deleted_at: None,
+                        private: row.get(10)?,
+                        tags: json_column(row, 11)?,
+                        author: row.get(12)?,
+                        source_kind: row.get(13)?,
+                        attributes: json_column(row, 14)?,
+                    })
+                },
+            )
+            .optional()
+            .context("Failed to query snippet")?;
+        let keyring = self.keyring.as_ref();
+        snippet
+            .map(|snippet| {
+                let encrypted = is_encrypted(keyring, snippet.private);
+                Ok(Snippet {
+                    content: open_content(keyring, encrypted, snippet.content.clone())?,
+                    ..snippet
+                })
+            })
+            .transpose()
+    }
+
+    fn save(&mut self, name: &SnippetName, snippet: &Snippet) -> Result<()> {
+        let source = snippet.source.as_ref();
+        let keyring = self.keyring.as_ref();
+        check_private(keyring, snippet)?;
+        let content = seal_content(keyring, is_encrypted(keyring, snippet.private), &snippet.content)?;
+        let tx = self.conn.savepoint()?;
+        let before = current_content(&tx, keyring, name)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO snippets
+             (name, content, created_at, updated_at, revision, description, language,
+              source_url, etag, last_modified, read_count, private, tags,
+              author, source_kind, attributes, last_accessed_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+            params![
+                name.as_str(),
+                content,
+                snippet.created_at,
+                snippet.updated_at,
+                snippet.revision,
+                snippet.description,
+                snippet.language,
+                source.map(|source| &source.url),
+                source.and_then(|source| source.etag.as_ref()),
+                source.and_then(|source| source.last_modified.as_ref()),
+                snippet.read_count,
+                snippet.private,
+                serde_json::to_string(&snippet.tags)?,
+                snippet.author,
+                snippet.source_kind,
+                serde_json::to_string(&snippet.attributes)?,
+                snippet.last_accessed_at,
+            ],
+        )
+        .context("Failed to insert snippet")?;
+        insert_audit(&tx, &AuditEntry::new(name, before.as_deref(), Some(&snippet.content)))?;
+        tx.commit().context("Failed to commit snippet")
+    }
+
+    fn remove(&mut self, name: &str) -> Result<bool> {
+        let tx = self.conn.savepoint()?;
+        let Some(before) = current_content(&tx, self.keyring.as_ref(), name)? else {
+            return Ok(false);
+        };
+        tx.execute(
+            "UPDATE snippets SET deleted_at = ?2 WHERE name = ?1",
+            params![name, chrono::Utc::now().to_rfc3339()],
+        )?;
+        insert_audit(&tx, &AuditEntry::new(name, Some(&before), None))?;
+        tx.commit().context("Failed to commit deletion")?;
+        Ok(true)
+    }
+
+    fn rename(&mut self, from: &str, to: &SnippetName) -> Result<bool> {
+        let tx = self.conn.savepoint()?;
+        let Some(content) = current_content(&tx, self.keyring.as_ref(), from)? else {
+            return Ok(false);
+        };
+        let exists: bool = tx
+            .query_row(
+                "SELECT COUNT(*) > 0 FROM snippets WHERE name = ?1 AND deleted_at IS NULL",
+                [to.as_str()],
+                |row| row.get(0),
+            )
+            .context("Failed to query snippet")?;
+        if exists {
+            bail!("Snippet '{to}' already exists");
+        }
+        tx.execute("DELETE FROM snippets WHERE name = ?1", [to.as_str()])?;
+        tx.execute("UPDATE snippets SET name = ?2 WHERE name = ?1", params![from, to.as_str()])
+            .context("Failed to rename snippet")?;
+        insert_audit(&tx, &AuditEntry::new(from, Some(&content), None))?;
+        insert_audit(&tx, &AuditEntry::new(to, None, Some(&content)))?;
+        tx.commit().context("Failed to commit rename")?;
+        Ok(true)
+    }
+
+    fn names(&self) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT name FROM snippets WHERE deleted_at IS NULL ORDER BY name")?;
+        let names = stmt.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+        Ok(names)
+    }
+
+    fn trash(&self) -> Result<Vec<TrashedSnippet>> {
+        let mut stmt =
+            self.conn.prepare("SELECT name, deleted_at FROM snippets WHERE deleted_at IS NOT NULL ORDER BY name")?;
+        let trash = stmt
+            .query_map([], |row| {
+                Ok(TrashedSnippet {
+                    name: row.get(0)?,
+                    deleted_at: row.get(1)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(trash)
+    }
+
+    fn restore(&mut self, name: &str) -> Result<bool> {
+        let tx = self.conn.savepoint()?;
+        let Some(content) = stored_content(&tx, self.keyring.as_ref(), name, true)? else {
+            return Ok(false);
+        };
+        tx.execute("UPDATE snippets SET deleted_at = NULL WHERE name = ?1", [name])?;
+        insert_audit(&tx, &AuditEntry::new(name, None, Some(&content)))?;
+        tx.commit().context("Failed to commit restore")?;
+        Ok(true)
+    }
+
+    fn purge(&mut self, name: &str) -> Result<bool> {
+        let purged = self
+            .conn
+            .execute("DELETE FROM snippets WHERE name = ?1 AND deleted_at IS NOT NULL", [name])
+            .context("Failed to purge snippet")?;
+        Ok(purged > 0)
+    }
+
+    fn record_read(&mut self, name: &str) -> Result<()> {
+        self.conn
+            .execute(
+                "UPDATE snippets SET read_count = read_count + 1, last_accessed_at = ?2
+                 WHERE name = ?1 AND deleted_at IS NULL",
+                params![name, chrono::Utc::now().to_rfc3339()],
+            )
+            .context("Failed to count read")?;
+        Ok(())
+    }
+
+    fn record_source(&mut self, name: &str, source: &Source) -> Result<()> {
+        self.conn
+            .execute(
+                "UPDATE snippets SET source_url = ?2, etag = ?3, last_modified = ?4
+                 WHERE name = ?1 AND deleted_at IS NULL",
+                params![name, source.url, source.etag, source.last_modified],
+            )
+            .context("Failed to update source")?;
+        Ok(())
+    }
+
+    fn usage(&self) -> Result<Vec<Usage>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name, read_count, last_accessed_at, created_at, updated_at FROM snippets
+             WHERE deleted_at IS NULL ORDER BY name",
+        )?;
+        let usage = stmt
+            .query_map([], |row| {
+                Ok(Usage {
+                    name: row.get(0)?,
+                    read_count: row.get(1)?,
+                    last_accessed_at: row.get(2)?,
+                    created_at: row.get(3)?,
+                    updated_at: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(usage)
+    }
+
+    fn init(&mut self, encryption: Option<(&str, EncryptionScope)>) -> Result<()> {
+        match encryption {
+            Some(_) if self.keyring.is_some() => {
+                bail!("The store is already encrypted: use `rekey` to change the passphrase")
+            }
+            Some((passphrase, scope)) => self.reencrypt(Keyring::create(passphrase, scope)?),
+            None => Ok(()),
+        }
+    }
+
+    fn rekey(&mut self, passphrase: &str) -> Result<()> {
+        let Some(keyring) = &self.keyring else {
+            bail!("The store is not encrypted: use `init --encrypt` to encrypt it");
+        };
+        keyring.cipher()?;
+        let keyring = Keyring::create(passphrase, keyring.scope())?;
+        self.reencrypt(keyring)
+    }
+
+    fn unlock(&self) -> Result<()> {
+        self.keyring.as_ref().map(Keyring::cipher).transpose()?;
+        Ok(())
+    }
+
+    fn batch(&mut self, operations: &mut dyn FnMut(&mut dyn SnippetStorage) -> Result<()>) -> Result<()> {
+        if !self.conn.is_autocommit() {
+            return operations(self);
+        }
+        self.conn.execute_batch("BEGIN IMMEDIATE").context("Failed to begin batch")?;
+        let outcome =
+            operations(self).and_then(|()| self.conn.execute_batch("COMMIT").context("Failed to commit batch"));
+        if outcome.is_err() && !self.conn.is_autocommit() {
+            let _ = self.conn.execute_batch("ROLLBACK");
+        }
+        outcome
+    }
+
+    fn audit_log(&self) -> Result<Vec<AuditEntry>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT timestamp, user, host, operation, name, hash_before, hash_after FROM audit_log ORDER BY id",
+        )?;
+        let entries = stmt
+            .query_map([], |row| {
+                Ok(AuditEntry {
+                    timestamp: row.get(0)?,
+                    user: row.get(1)?,
+                    host: row.get(2)?,
+                    operation: row.get(3)?,
+                    name: row.get(4)?,
+                    hash_before: row.get(5)?,
+                    hash_after: row.get(6)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()
+            .context("Failed to query audit log")?;
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::test_keyring;
+
+    fn name(name: &str) -> SnippetName {
+        name.parse().unwrap()
+    }
+
+    #[test]
+    fn legacy_tuple_snippets_are_loaded() {
+        let store: SnippetStore =
+            serde_json::from_str(r#"{"snippets": {"a": ["code", "2024-01-01T00:00:00+00:00"]}}"#).unwrap();
+        let snippet = &store.snippets["a"];
+        assert_eq!(snippet.content, "code");
+        assert_eq!(snippet.revision, 1);
+        assert_eq!(snippet.source, None);
+    }
+
+    #[test]
+    fn sqlite_round_trips_source() {
+        let mut storage = SqliteStorage::open(":memory:").unwrap();
+        let mut snippet = Snippet::new("code".into());
+        snippet.tags = vec!["cli".into()];
+        snippet.author = Some("Jane Doe".into());
+        snippet.source_kind = Some(SourceKind::Url);
+        snippet.attributes.insert("license".into(), "MIT".into());
+        snippet.source = Some(Source {
+            url: "https://example.com/a.rs".into(),
+            etag: Some("\"abc\"".into()),
+            last_modified: None,
+        });
+        storage.save(&name("a"), &snippet).unwrap();
+        assert_eq!(storage.get("a").unwrap(), Some(snippet));
+        assert!(storage.remove("a").unwrap());
+        assert_eq!(storage.get("a").unwrap(), None);
+    }
+
+    #[test]
+    fn sqlite_audits_mutations() {
+        let mut storage = SqliteStorage::open(":memory:").unwrap();
+        storage.save(&name("a"), &Snippet::new("one".into())).unwrap();
+        storage.save(&name("a"), &Snippet::new("two".into())).unwrap();
+        assert!(storage.remove("a").unwrap());
+        assert!(!storage.remove("a").unwrap());
+        let log = storage.audit_log().unwrap();
+        let operations: Vec<_> = log.iter().map(|entry| entry.operation).collect();
+        assert_eq!(operations, [AuditOperation::Create, AuditOperation::Update, AuditOperation::Delete]);
+        assert_eq!(log[1].hash_before, log[0].hash_after);
+        assert!(storage.conn.execute("DELETE FROM audit_log", []).is_err());
+    }
+
+    #[test]
+    fn read_only_sqlite_is_not_migrated() {
+        let path = std::env::temp_dir().join(format!("snippets-read-only-{}.sqlite", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let tables = || -> Vec<String> {
+            let conn = Connection::open(&path).unwrap();
+            let mut statement = conn.prepare("SELECT name FROM sqlite_master WHERE type = 'table'").unwrap();
+            statement.query_map([], |row| row.get(0)).unwrap().map(Result::unwrap).collect()
+        };
+        let legacy = "CREATE TABLE snippets (name TEXT PRIMARY KEY, content TEXT NOT NULL, created_at TEXT NOT NULL)";
+        Connection::open(&path).unwrap().execute_batch(legacy).unwrap();
+        assert!(SqliteStorage::open_read_only(&path, PassphraseSource::None).is_err());
+        assert_eq!(tables(), ["snippets"]);
+
+        SqliteStorage::open(&path).unwrap().save(&name("a"), &Snippet::new("code".into())).unwrap();
+        let mut storage = SqliteStorage::open_read_only(&path, PassphraseSource::None).unwrap();
+        assert_eq!(storage.names().unwrap(), ["a"]);
+        assert!(storage.save(&name("b"), &Snippet::new("code".into())).is_err());
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn sqlite_renames_across_namespaces() {
+        let mut storage = SqliteStorage::open(":memory:").unwrap();
+        storage.save(&name("let-else"), &Snippet::new("one".into())).unwrap();
+        storage.save(&name("rust/newtype"), &Snippet::new("two".into())).unwrap();
+        assert!(storage.rename("let-else", &name("rust/patterns/let-else")).unwrap());
+        assert!(!storage.rename("let-else", &name("other")).unwrap());
+        assert!(storage.rename("rust/newtype", &name("rust/patterns/let-else")).is_err());
+        assert_eq!(storage.names().unwrap(), ["rust/newtype", "rust/patterns/let-else"]);
+        assert_eq!(storage.audit_log().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn sqlite_counts_reads_without_auditing() {
+        let mut storage = SqliteStorage::open(":memory:").unwrap();
+        storage.save(&name("a"), &Snippet::new("code".into())).unwrap();
+        storage.record_read("a").unwrap();
+        storage.record_read("a").unwrap();
+        storage.record_read("missing").unwrap();
+        let snippet = storage.get("a").unwrap().unwrap();
+        assert_eq!(snippet.read_count, 2);
+        assert!(snippet.last_accessed_at.is_some());
+        assert_eq!(storage.audit_log().unwrap().len(), 1);
+        let usage = storage.usage().unwrap();
+        assert_eq!(usage.len(), 1);
+        assert_eq!((usage[0].read_count, &usage[0].last_accessed_at), (2, &snippet.last_accessed_at));
+    }
+
+    #[test]
+    fn sqlite_encrypts_private_content() {
+        let mut storage = SqliteStorage::open(":memory:").unwrap();
+        let mut private = Snippet::new("internal host".into());
+        private.private = true;
+        assert!(storage.save(&name("a"), &private).is_err());
+
+        storage.save(&name("b"), &Snippet::new("public".into())).unwrap();
+        storage.reencrypt(test_keyring("secret", EncryptionScope::Private)).unwrap();
+        storage.save(&name("a"), &private).unwrap();
+        assert_eq!(storage.get("a").unwrap(), Some(private));
+        let raw = |name: &str| -> String {
+            let query = "SELECT content FROM snippets WHERE name = ?1";
+            storage.conn.query_row(query, [name], |row| row.get(0)).unwrap()
+        };
+        assert!(!raw("a").contains("internal"));
+        assert_eq!(raw("b"), "public");
+    }
+
+    #[test]
+    fn unlocking_checks_the_passphrase_before_any_read() {
+        let path = std::env::temp_dir().join(format!("snippets-unlock-{}.sqlite", std::process::id()));
+        let mut storage = SqliteStorage::open(&path).unwrap();
+        storage.unlock().unwrap();
+        storage.reencrypt(test_keyring("secret", EncryptionScope::Private)).unwrap();
+        drop(storage);
+
+        let locked = SqliteStorage::open(&path).unwrap();
+        assert_eq!(locked.unlock().unwrap_err().downcast_ref::<CryptoError>(), Some(&CryptoError::Locked));
+        let wrong = SqliteStorage::open_with_passphrase(&path, PassphraseSource::Value("guess".into())).unwrap();
+        assert_eq!(wrong.unlock().unwrap_err().downcast_ref::<CryptoError>(), Some(&CryptoError::WrongPassphrase));
+        let unlocked = SqliteStorage::open_with_passphrase(&path, PassphraseSource::Value("secret".into())).unwrap();
+        unlocked.unlock().unwrap();
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn removed_snippets_are_trashed_and_restorable() {
+        let path = std::env::temp_dir().join(format!("snippets-trash-{}.json", std::process::id()));
+        let backends: [Box<dyn SnippetStorage>; 2] =
+            [Box::new(JsonStorage::open(&path).unwrap()), Box::new(SqliteStorage::open(":memory:").unwrap())];
+        for mut storage in backends {
+            storage.save(&name("a"), &Snippet::new("one".into())).unwrap();
+            storage.save(&name("b"), &Snippet::new("two".into())).unwrap();
+            assert!(storage.remove("a").unwrap());
+            assert!(!storage.remove("a").unwrap());
+            assert_eq!(storage.get("a").unwrap(), None);
+            assert_eq!(storage.names().unwrap(), ["b"]);
+            assert_eq!(storage.trash().unwrap().iter().map(|t| t.name.as_str()).collect::<Vec<_>>(), ["a"]);
+
+            assert!(storage.restore("a").unwrap());
+            assert!(!storage.restore("a").unwrap());
+            assert_eq!(storage.get("a").unwrap().unwrap().content, "one");
+
+            storage.remove("b").unwrap();
+            storage.rename("a", &name("b")).unwrap();
+            assert_eq!(storage.get("b").unwrap().unwrap().content, "one");
+            storage.remove("b").unwrap();
+            assert!(storage.purge("b").unwrap());
+            assert!(storage.trash().unwrap().is_empty());
+            let operations: Vec<_> = storage.audit_log().unwrap().iter().map(|entry| entry.operation).collect();
+            assert_eq!(
+                operations,
+                [
+                    AuditOperation::Create,
+                    AuditOperation::Create,
+                    AuditOperation::Delete,
+                    AuditOperation::Create,
+                    AuditOperation::Delete,
+                    AuditOperation::Delete,
+                    AuditOperation::Create,
+                    AuditOperation::Delete,
+                ]
+            );
+        }
+        let _ = fs::remove_file(path.with_extension("audit.jsonl"));
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn sources_are_recorded_without_auditing() {
+        let path = std::env::temp_dir().join(format!("snippets-source-{}.json", std::process::id()));
+        let backends: [Box<dyn SnippetStorage>; 2] =
+            [Box::new(JsonStorage::open(&path).unwrap()), Box::new(SqliteStorage::open(":memory:").unwrap())];
+        let source = Source {
+            url: "https://example.com/a.rs".into(),
+            etag: Some("\"v2\"".into()),
+            last_modified: None,
+        };
+        for mut storage in backends {
+            storage.save(&name("a"), &Snippet::new("code".into())).unwrap();
+            storage.record_source("a", &source).unwrap();
+            storage.record_source("missing", &source).unwrap();
+            let snippet = storage.get("a").unwrap().unwrap();
+            assert_eq!((snippet.source.as_ref(), snippet.revision), (Some(&source), 1));
+            assert_eq!(storage.audit_log().unwrap().len(), 1);
+            assert_eq!(storage.names().unwrap(), ["a"]);
+        }
+        let _ = fs::remove_file(path.with_extension("audit.jsonl"));
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn failed_batches_change_nothing() {
+        let path = std::env::temp_dir().join(format!("snippets-batch-{}.json", std::process::id()));
+        let backends: [Box<dyn SnippetStorage>; 2] =
+            [Box::new(JsonStorage::open(&path).unwrap()), Box::new(SqliteStorage::open(":memory:").unwrap())];
+        for mut storage in backends {
+            storage.save(&name("a"), &Snippet::new("one".into())).unwrap();
+            storage.save(&name("b"), &Snippet::new("two".into())).unwrap();
+            let failed = storage.batch(&mut |storage| {
+                storage.remove("a")?;
+                storage.rename("b", &name("c"))?;
+                bail!("interrupted")
+            });
+            assert!(failed.is_err());
+            assert_eq!(storage.names().unwrap(), ["a", "b"]);
+            assert_eq!(storage.audit_log().unwrap().len(), 2);
+
+            let mut remove_all = |storage: &mut dyn SnippetStorage| {
+                ["a", "b"].iter().try_for_each(|name| storage.remove(name).map(drop))
+            };
+            storage.batch(&mut remove_all).unwrap();
+            assert!(storage.names().unwrap().is_empty());
+            assert_eq!(storage.audit_log().unwrap().len(), 4);
+        }
+        let _ = fs::remove_file(path.with_extension("audit.jsonl"));
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn json_encrypts_whole_store() {
+        let path = std::env::temp_dir().join(format!("snippets-encrypted-{}.json", std::process::id()));
+        let mut storage = JsonStorage::open(&path).unwrap();
+        storage.save(&name("a"), &Snippet::new("internal host".into())).unwrap();
+        let keyring = test_keyring("secret", EncryptionScope::All);
+        let header = keyring.header().clone();
+        storage.reencrypt(keyring).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("internal"));
+
+        let err = JsonStorage::open(&path).err().unwrap();
+        assert_eq!(err.downcast_ref::<CryptoError>(), Some(&CryptoError::Locked));
+        let wrong = JsonStorage::open_with_passphrase(&path, PassphraseSource::Value("guess".into()));
+        assert_eq!(wrong.err().unwrap().downcast_ref::<CryptoError>(), Some(&CryptoError::WrongPassphrase));
+
+        let reopened = JsonStorage::open_with_passphrase(&path, PassphraseSource::Value("secret".into())).unwrap();
+        assert_eq!(reopened.get("a").unwrap().unwrap().content, "internal host");
+        assert_eq!(reopened.store.encryption, Some(header));
+        let _ = fs::remove_file(path.with_extension("audit.jsonl"));
+        let _ = fs::remove_file(path);
+    }
+}
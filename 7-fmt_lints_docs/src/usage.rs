@@ -0,0 +1,118 @@
+//! Usage of snippets: how often and how recently they were read, for `list --sort` and `prune`.
+//!
+//! Reads are counted by [`SnippetStorage::record_read`](crate::storage::SnippetStorage::record_read)
+//! unless `usage.track_reads` is turned off, e.g. for a store on read-only media.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use std::cmp::Reverse;
+
+use crate::audit::parse_time;
+
+/// Usage of a stored snippet, available without decrypting its content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Usage {
+    /// Name of the snippet.
+    pub name: String,
+    /// Number of times the snippet was read.
+    pub read_count: u64,
+    /// Time of the last read in RFC 3339 format.
+    pub last_accessed_at: Option<String>,
+    /// Creation time in RFC 3339 format.
+    pub created_at: String,
+    /// Time of the last content change in RFC 3339 format.
+    pub updated_at: Option<String>,
+}
+
+impl Usage {
+    /// Time the snippet was last read, changed or created, whichever is latest.
+    ///
+    /// `None` if none of the times can be parsed, e.g. for snippets of old stores.
+    pub fn last_used(&self) -> Option<DateTime<Utc>> {
+        [Some(&self.created_at), self.updated_at.as_ref(), self.last_accessed_at.as_ref()]
+            .into_iter()
+            .flatten()
+            .filter_map(|time| DateTime::parse_from_rfc3339(time).ok())
+            .map(|time| time.with_timezone(&Utc))
+            .max()
+    }
+
+    /// Whether the snippet was last used before `cutoff`.
+    pub fn is_unused_since(&self, cutoff: DateTime<Utc>) -> bool {
+        self.last_used().is_some_and(|last_used| last_used < cutoff)
+    }
+}
+
+/// Order of `list --sort`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum UsageOrder {
+    /// Most recently read first.
+    Recent,
+    /// Most often read first.
+    Popular,
+}
+
+/// Sorts `usage` by `order`, breaking ties by name.
+pub fn sort_usage(usage: &mut [Usage], order: UsageOrder) {
+    match order {
+        UsageOrder::Recent => usage.sort_by(|a, b| {
+            let last_read = |usage: &Usage| usage.last_accessed_at.as_ref().and_then(|time| parse_time(time).ok());
+            Reverse(last_read(a)).cmp(&Reverse(last_read(b))).then_with(|| a.name.cmp(&b.name))
+        }),
+        UsageOrder::Popular => {
+            usage.sort_by(|a, b| b.read_count.cmp(&a.read_count).then_with(|| a.name.cmp(&b.name)));
+        }
+    }
+}
+
+/// Parses the `--unused-since` cutoff: an age such as `180d`, `12w` or `36h`, or a time accepted by
+/// [`parse_time`].
+pub fn parse_cutoff(since: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let age = since.strip_suffix(['h', 'd', 'w']).and_then(|count| count.parse::<i64>().ok());
+    let Some(count) = age else {
+        return parse_time(since).context("Expected an age such as 180d, 12w or 36h, or a date");
+    };
+    let age = match since.chars().last() {
+        Some('h') => Duration::try_hours(count),
+        Some('d') => Duration::try_days(count),
+        _ => Duration::try_weeks(count),
+    };
+    age.and_then(|age| now.checked_sub_signed(age))
+        .with_context(|| format!("Age '{since}' is too large"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(name: &str, read_count: u64, last_accessed_at: Option<&str>) -> Usage {
+        Usage {
+            name: name.to_owned(),
+            read_count,
+            last_accessed_at: last_accessed_at.map(str::to_owned),
+            created_at: "2024-01-01T00:00:00+00:00".to_owned(),
+            updated_at: None,
+        }
+    }
+
+    #[test]
+    fn usage_is_sorted_and_aged() {
+        let mut all = vec![
+            usage("never", 0, None),
+            usage("old", 5, Some("2024-02-01T00:00:00+00:00")),
+            usage("new", 1, Some("2024-06-01T00:00:00+00:00")),
+        ];
+        sort_usage(&mut all, UsageOrder::Recent);
+        assert_eq!(all.iter().map(|usage| usage.name.as_str()).collect::<Vec<_>>(), ["new", "old", "never"]);
+        sort_usage(&mut all, UsageOrder::Popular);
+        assert_eq!(all.iter().map(|usage| usage.name.as_str()).collect::<Vec<_>>(), ["old", "new", "never"]);
+
+        let now = parse_time("2024-07-01").unwrap();
+        assert_eq!(parse_cutoff("30d", now).unwrap(), parse_time("2024-06-01").unwrap());
+        assert_eq!(parse_cutoff("2024-03-01", now).unwrap(), parse_time("2024-03-01").unwrap());
+        assert!(parse_cutoff("6 months", now).is_err());
+        let cutoff = parse_cutoff("10w", now).unwrap();
+        let unused: Vec<_> = all.iter().filter(|usage| usage.is_unused_since(cutoff)).map(|u| &u.name).collect();
+        assert_eq!(unused, ["old", "never"]);
+    }
+}
@@ -0,0 +1,273 @@
+//! Downloading snippet content over HTTP(S) with timeouts, a size limit and content-type checks.
+//!
+//! Shared by every lab that implements `--download`, so that the limits are enforced the same way everywhere.
+
+use anyhow::{Context, Result, bail};
+use encoding_rs::{Encoding, UTF_8};
+use reqwest::{
+    StatusCode,
+    blocking::{Client, Response},
+    header::{CONTENT_TYPE, ETAG, HeaderName, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+};
+use std::{io::Read, time::Duration};
+
+/// Limits and policies applied when downloading a snippet.
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// Maximum time to wait for the connection to be established.
+    pub connect_timeout: Duration,
+    /// Maximum time for the whole request, from connecting until the body has been read.
+    pub read_timeout: Duration,
+    /// Maximum accepted size of the response body in bytes.
+    pub max_size: u64,
+    /// Accept responses whose content type does not look like text.
+    pub allow_binary: bool,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            max_size: 1024 * 1024,
+            allow_binary: false,
+        }
+    }
+}
+
+/// Cache validators of a downloaded response, sent back with a conditional request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    /// Value of the `ETag` header.
+    pub etag: Option<String>,
+    /// Value of the `Last-Modified` header.
+    pub last_modified: Option<String>,
+}
+
+/// Raw content fetched from a source, before decoding.
+#[derive(Debug, Clone)]
+pub struct Fetched {
+    /// Response body, at most [`DownloadOptions::max_size`] bytes.
+    pub body: Vec<u8>,
+    /// Charset given with the content type, if any.
+    pub charset: Option<String>,
+    /// Validators to send when fetching the source again.
+    pub validators: Validators,
+}
+
+impl Fetched {
+    /// Decodes the body using its charset, defaulting to UTF-8.
+    pub fn decode(&self, options: &DownloadOptions) -> Result<String> {
+        decode_body(&self.body, self.charset.as_deref(), options.allow_binary)
+    }
+}
+
+/// Downloads `url` and decodes it as text.
+///
+/// Non-2xx responses, bodies larger than [`DownloadOptions::max_size`] and
+/// non-text content types (unless [`DownloadOptions::allow_binary`] is set)
+/// are rejected instead of being stored as a snippet.
+pub fn download_text(url: &str, options: &DownloadOptions) -> Result<String> {
+    match fetch(url, &Validators::default(), options)? {
+        Some(fetched) => fetched.decode(options),
+        None => bail!("Server responded with {} for {url}", StatusCode::NOT_MODIFIED),
+    }
+}
+
+/// Downloads `url` using a conditional request built from `validators`.
+///
+/// Returns `None` if the server reports that the content has not been modified.
+pub fn fetch(url: &str, validators: &Validators, options: &DownloadOptions) -> Result<Option<Fetched>> {
+    let client = Client::builder()
+        .connect_timeout(options.connect_timeout)
+        .timeout(options.read_timeout)
+        .build()
+        .context("Failed to build HTTP client")?;
+    let mut request = client.get(url);
+    if let Some(etag) = &validators.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+    let response = request
+        .send()
+        .with_context(|| format!("Failed to download snippet from {url}"))?;
+
+    let status = response.status();
+    if status == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        bail!("Server responded with {status} for {url}");
+    }
+
+    let validators = Validators {
+        etag: header_value(&response, ETAG),
+        last_modified: header_value(&response, LAST_MODIFIED),
+    };
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok());
+    if let Some(mime) = &content_type
+        && !options.allow_binary
+        && !is_text_mime(mime)
+    {
+        bail!("Refusing to save '{mime}' content from {url} as a snippet (use --allow-binary to override)");
+    }
+
+    if let Some(length) = response.content_length()
+        && length > options.max_size
+    {
+        bail!("Snippet at {url} is {length} bytes, which exceeds the limit of {} bytes", options.max_size);
+    }
+
+    let body = read_limited(response, url, options)?;
+    if content_type.is_none() && !options.allow_binary && body.contains(&0) {
+        bail!("Downloaded content from {url} looks binary (use --allow-binary to override)");
+    }
+
+    let charset = content_type
+        .as_ref()
+        .and_then(|mime| mime.get_param(mime::CHARSET))
+        .map(|charset| charset.to_string());
+    Ok(Some(Fetched {
+        body,
+        charset,
+        validators,
+    }))
+}
+
+/// Reads at most [`DownloadOptions::max_size`] bytes, failing if there is more.
+pub fn read_limited(reader: impl Read, url: &str, options: &DownloadOptions) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    reader
+        .take(options.max_size + 1)
+        .read_to_end(&mut body)
+        .with_context(|| format!("Failed to read snippet from {url}"))?;
+    if body.len() as u64 > options.max_size {
+        bail!("Snippet at {url} exceeds the limit of {} bytes", options.max_size);
+    }
+    Ok(body)
+}
+
+fn header_value(response: &Response, name: HeaderName) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+/// Returns `true` if `mime` describes content that can be stored as a snippet.
+fn is_text_mime(mime: &mime::Mime) -> bool {
+    if mime.type_() == mime::TEXT {
+        return true;
+    }
+    if mime.type_() != mime::APPLICATION {
+        return false;
+    }
+    if matches!(mime.suffix().map(|suffix| suffix.as_str()), Some("json" | "xml")) {
+        return true;
+    }
+    matches!(
+        mime.subtype().as_str(),
+        "json"
+            | "xml"
+            | "javascript"
+            | "ecmascript"
+            | "toml"
+            | "yaml"
+            | "x-yaml"
+            | "sql"
+            | "x-sh"
+            | "x-shellscript"
+            | "x-httpd-php"
+            | "x-python"
+            | "x-rust"
+    )
+}
+
+/// Decodes `body` using the given charset label, defaulting to UTF-8.
+fn decode_body(body: &[u8], charset: Option<&str>, lossy: bool) -> Result<String> {
+    let encoding = match charset {
+        Some(label) => {
+            Encoding::for_label(label.as_bytes()).with_context(|| format!("Unsupported charset '{label}'"))?
+        }
+        None => UTF_8,
+    };
+    let (text, _, had_errors) = encoding.decode(body);
+    if had_errors && !lossy {
+        bail!("Downloaded snippet is not valid {}", encoding.name());
+    }
+    Ok(text.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Write, net::TcpListener, thread};
+
+    fn serve_once(status: &str, content_type: Option<&str>, body: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/snippet", listener.local_addr().unwrap());
+        let mut head = format!("HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n", body.len());
+        if let Some(content_type) = content_type {
+            head.push_str(&format!("Content-Type: {content_type}\r\n"));
+        }
+        head.push_str("\r\n");
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(body).unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn downloads_text() {
+        let url = serve_once("200 OK", Some("text/plain; charset=utf-8"), b"fn main() {}");
+        assert_eq!(download_text(&url, &DownloadOptions::default()).unwrap(), "fn main() {}");
+    }
+
+    #[test]
+    fn decodes_the_charset() {
+        let url = serve_once("200 OK", Some("text/plain; charset=windows-1251"), b"// \xcf\xf0\xe8\xe2\xb3\xf2");
+        assert_eq!(download_text(&url, &DownloadOptions::default()).unwrap(), "// Привіт");
+    }
+
+    #[test]
+    fn rejects_error_statuses() {
+        let url = serve_once("404 Not Found", Some("text/html"), b"<h1>Not Found</h1>");
+        let err = download_text(&url, &DownloadOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("404"));
+    }
+
+    #[test]
+    fn rejects_binary_content_unless_allowed() {
+        let url = serve_once("200 OK", Some("image/png"), b"\x89PNG");
+        assert!(download_text(&url, &DownloadOptions::default()).is_err());
+
+        let url = serve_once("200 OK", Some("image/png"), b"\x89PNG");
+        let options = DownloadOptions {
+            allow_binary: true,
+            ..DownloadOptions::default()
+        };
+        assert!(download_text(&url, &options).is_ok());
+    }
+
+    #[test]
+    fn rejects_oversized_bodies() {
+        let url = serve_once("200 OK", Some("text/plain"), b"0123456789");
+        let options = DownloadOptions {
+            max_size: 4,
+            ..DownloadOptions::default()
+        };
+        assert!(download_text(&url, &options).is_err());
+    }
+}